@@ -12,11 +12,12 @@ use tokio::runtime::Runtime;
 use warp::Filter;
 
 use async_std::sync::Receiver;
-use clobber::{Job, JobStatus, PidController, WorkerPool, WorkerPoolCommand};
+use clobber::{Job, JobStatus, PidController, Setpoint, WorkerPool, WorkerPoolCommand};
 use std::{
     cmp::{max, Ordering::Equal},
     collections::{HashMap, VecDeque},
     fmt::{Debug, Formatter},
+    sync::Arc,
 };
 
 fn main() {
@@ -24,7 +25,8 @@ fn main() {
     start_test_server();
 
     task::block_on(async {
-        let goal_rps = 4000f32;
+        // ramp up to 4000 rps over the first 30 seconds, then hold
+        let goal_rps = Setpoint::Ramp { from: 500.0, to: 4000.0, over: Duration::from_secs(30) };
         let url = "http://localhost:8000/hello/server";
         let tick_rate = Duration::from_secs_f32(0.1);
         let (send, recv) = channel(1024); // todo max workers?
@@ -32,11 +34,13 @@ fn main() {
         let mut float_workers = 1.0; // this is the thing we're actually driving
         let mut num_workers = 1;
         let mut pid = PidController::new((0.00001, 0.0, 0.0));
-        let mut pool = WorkerPool::new(load_url, send, 1);
+        let mut pool =
+            WorkerPool::with_context(load_url, send, 1, || Arc::new(surf::Client::new()), |_| ());
         let mut request_tracker = RequestTracker::new();
         let command = pool.command_channel();
 
         task::spawn(async move {
+            let start = Instant::now();
             let mut tick = Tick::new(tick_rate);
 
             while let Ok(metric) = recv.recv().await {
@@ -52,7 +56,7 @@ fn main() {
                         float_workers
                     );
 
-                    pid.update(goal_rps, tick.tracker.rps());
+                    pid.update(goal_rps.at(start.elapsed()), tick.tracker.rps());
 
                     float_workers += pid.output();
                     if float_workers.floor() as usize != num_workers {
@@ -141,7 +145,8 @@ impl Debug for RequestTracker {
 
 /// This is a single worker method that makes constant HTTP GET requests
 /// until the Receiver channel gets a close method.
-async fn load_url(job: Job<(&str, Option<usize>), Metric>) -> JobStatus {
+/// Each worker slot keeps its own client, so connections are reused between jobs.
+async fn load_url(job: Job<(&str, Option<usize>), Metric, Arc<surf::Client>>) -> JobStatus {
     let (url, mut count) = job.task;
 
     let mut get_status = || {
@@ -169,11 +174,12 @@ async fn load_url(job: Job<(&str, Option<usize>), Metric>) -> JobStatus {
         match get_status() {
             JobStatus::Done => return JobStatus::Done,
             JobStatus::Stopped => return JobStatus::Stopped,
+            JobStatus::Failed => return JobStatus::Failed,
             JobStatus::Running => {}
         }
 
         let start = Instant::now();
-        let status = match surf::get(url).await {
+        let status = match job.context.get(url).await {
             Ok(res) => res.status(),
             Err(err) => err.status(),
         };
@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+use crate::rng::Rng;
+
+/// # Arrivals
+///
+/// When tasks should start in an open-loop `WorkerPool`.
+///
+/// A closed-loop pool starts the next task when a worker frees up, so a slow target quietly
+/// lowers the offered load and hides its own latency (coordinated omission). An open-loop pool
+/// starts tasks on a schedule instead, whether or not earlier tasks have finished. See
+/// `WorkerPool::set_open_loop`.
+#[derive(Debug, Clone)]
+pub enum Arrivals {
+    /// Evenly spaced arrivals, `rate` per second
+    Constant(f32),
+    /// Poisson-distributed arrivals averaging `rate` per second. The same seed always produces
+    /// the same schedule.
+    Poisson { rate: f32, seed: u64 },
+    /// Arrivals at these offsets from the start, in order. Replays a recorded schedule.
+    Schedule(Vec<Duration>),
+}
+
+/// Walks through the arrival times of a schedule
+pub(crate) struct ArrivalClock {
+    arrivals: Arrivals,
+    rng: Rng,
+    start: Instant,
+    /// How many arrivals have been passed
+    index: usize,
+    next: Option<Instant>,
+}
+
+impl ArrivalClock {
+    pub fn new(arrivals: Arrivals, start: Instant) -> Self {
+        let seed = match arrivals {
+            Arrivals::Poisson { seed, .. } => seed,
+            _ => 0,
+        };
+
+        let mut clock = Self { arrivals, rng: Rng::new(seed), start, index: 0, next: None };
+        clock.next = match &clock.arrivals {
+            Arrivals::Schedule(offsets) => offsets.first().map(|&offset| start + offset),
+            _ => clock.gap().map(|_| start),
+        };
+        clock
+    }
+
+    /// When the next task is scheduled to start, or `None` if nothing else will arrive
+    pub fn next_arrival(&self) -> Option<Instant> {
+        self.next
+    }
+
+    /// Moves on to the arrival after this one
+    pub fn advance(&mut self) {
+        self.index += 1;
+
+        if let Arrivals::Schedule(offsets) = &self.arrivals {
+            self.next = offsets.get(self.index).map(|&offset| self.start + offset);
+            return;
+        }
+
+        self.next = match (self.next, self.gap()) {
+            (Some(next), Some(gap)) => Some(next + gap),
+            _ => None,
+        };
+    }
+
+    /// Time between this arrival and the next
+    fn gap(&mut self) -> Option<Duration> {
+        match self.arrivals {
+            Arrivals::Constant(rate) if rate > 0.0 => {
+                Some(Duration::from_secs_f64(1.0 / rate as f64))
+            }
+            Arrivals::Poisson { rate, .. } if rate > 0.0 => {
+                Some(Duration::from_secs_f64(self.rng.exponential(1.0 / rate as f64)))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_spacing() {
+        let start = Instant::now();
+        let mut clock = ArrivalClock::new(Arrivals::Constant(4.0), start);
+
+        assert_eq!(clock.next_arrival(), Some(start));
+        clock.advance();
+        clock.advance();
+        assert_eq!(clock.next_arrival(), Some(start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn poisson_average_rate() {
+        let start = Instant::now();
+        let mut clock = ArrivalClock::new(Arrivals::Poisson { rate: 100.0, seed: 1 }, start);
+        for _ in 0..10_000 {
+            clock.advance();
+        }
+
+        // 10k arrivals at 100/s should take about 100s
+        let elapsed = clock.next_arrival().unwrap().duration_since(start).as_secs_f32();
+        assert!((elapsed - 100.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn schedule() {
+        let start = Instant::now();
+        let offsets =
+            vec![Duration::from_millis(5), Duration::from_millis(5), Duration::from_secs(1)];
+        let mut clock = ArrivalClock::new(Arrivals::Schedule(offsets), start);
+
+        assert_eq!(clock.next_arrival(), Some(start + Duration::from_millis(5)));
+        clock.advance();
+        assert_eq!(clock.next_arrival(), Some(start + Duration::from_millis(5)));
+        clock.advance();
+        assert_eq!(clock.next_arrival(), Some(start + Duration::from_secs(1)));
+        clock.advance();
+        assert_eq!(clock.next_arrival(), None);
+    }
+
+    #[test]
+    fn zero_rate_never_arrives() {
+        let clock = ArrivalClock::new(Arrivals::Constant(0.0), Instant::now());
+        assert_eq!(clock.next_arrival(), None);
+    }
+}
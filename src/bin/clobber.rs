@@ -0,0 +1,532 @@
+//! # clobber
+//!
+//! Puts a target under load, driving the number of workers with a PID controller to hit a
+//! request rate, or holding a fixed number of workers.
+//!
+//! ```text
+//! clobber http://localhost:8000/hello --rate 4000 --duration 60
+//! clobber http://localhost:8000/hello --concurrency 32 --duration 60
+//! ```
+//!
+//! A run can be saved with `--json` and later runs compared against it with `--baseline`,
+//! exiting with status 1 if any metric given to `--max-regression` got significantly worse:
+//!
+//! ```text
+//! clobber http://localhost:8000/hello --rate 4000 --json main.json
+//! clobber http://localhost:8000/hello --rate 4000 --baseline main.json --max-regression p99=10
+//! ```
+//!
+//! Built with the `cli` feature: `cargo install clobber --features cli`. Adding the
+//! `dashboard` feature gives a `--dashboard` flag, to watch the run live in the terminal and
+//! steer it from the keyboard.
+
+use async_std::{
+    future,
+    sync::{channel, Receiver},
+    task,
+};
+#[cfg(feature = "dashboard")]
+use clobber::dashboard::{Dashboard, DashboardInput};
+use clobber::{
+    http_worker, Check, CheckSummary, Checks, Histogram, HttpRequest, HttpResponse, Metric,
+    MetricsHandle, PidController, ReportBuilder, RunReport, Sample, Thresholds, WorkerPool,
+    WorkerPoolCommand,
+};
+use crossbeam_channel::Sender;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "clobber", about = "Puts a target under variable load")]
+struct Args {
+    /// Target url, e.g. http://localhost:8000/hello
+    target: String,
+
+    /// Requests per second to aim for
+    #[structopt(short, long, conflicts_with = "concurrency", required_unless = "concurrency")]
+    rate: Option<f32>,
+
+    /// Number of workers to run, instead of aiming for a rate
+    #[structopt(short, long)]
+    concurrency: Option<usize>,
+
+    /// How long to run, in seconds
+    #[structopt(short, long, default_value = "10")]
+    duration: f32,
+
+    /// Proportional gain
+    #[structopt(long, default_value = "0.00001")]
+    kp: f32,
+
+    /// Integral gain
+    #[structopt(long, default_value = "0")]
+    ki: f32,
+
+    /// Derivative gain
+    #[structopt(long, default_value = "0")]
+    kd: f32,
+
+    /// Most workers the controller may run
+    #[structopt(long, default_value = "1000")]
+    max_workers: usize,
+
+    /// How often to update the controller and progress line, in milliseconds
+    #[structopt(long, default_value = "100")]
+    tick: u64,
+
+    /// Give up on a request after this many milliseconds
+    #[structopt(long, default_value = "10000")]
+    timeout: u64,
+
+    /// HTTP method
+    #[structopt(short = "X", long, default_value = "GET")]
+    method: String,
+
+    /// Extra request header, e.g. "Accept: text/plain". May be repeated.
+    #[structopt(short = "H", long = "header")]
+    headers: Vec<String>,
+
+    /// Request body
+    #[structopt(long)]
+    body: Option<String>,
+
+    /// Open a new connection for every request
+    #[structopt(long)]
+    no_keep_alive: bool,
+
+    /// Statuses that count as success, e.g. 200-299 or 204
+    #[structopt(long, default_value = "200-399")]
+    expect_status: String,
+
+    /// Fail responses whose body doesn't contain this text
+    #[structopt(long)]
+    expect_body: Option<String>,
+
+    /// Fail responses that take longer than this many milliseconds
+    #[structopt(long)]
+    max_latency: Option<u64>,
+
+    /// Fail responses with a body bigger than this many bytes
+    #[structopt(long)]
+    max_body_size: Option<usize>,
+
+    /// Back off workers whenever more than this fraction of a tick's responses fail, e.g. 0.05
+    #[structopt(long)]
+    max_error_rate: Option<f32>,
+
+    /// Write a report of the run to this file as JSON
+    #[structopt(long, parse(from_os_str))]
+    json: Option<PathBuf>,
+
+    /// Write the run's timeline (rate, workers, controller terms per tick) to this file as CSV
+    #[structopt(long, parse(from_os_str))]
+    csv: Option<PathBuf>,
+
+    /// Write the run's totals to this file as CSV
+    #[structopt(long, parse(from_os_str))]
+    totals_csv: Option<PathBuf>,
+
+    /// Compare the run against a report saved earlier with --json
+    #[structopt(long, parse(from_os_str))]
+    baseline: Option<PathBuf>,
+
+    /// Fail the comparison if a metric gets worse than the baseline by more than this many
+    /// percent, e.g. p99=10 or throughput=5. May be repeated. Metrics are throughput, mean,
+    /// p50, p90, p99, p999, max, and errors (in percentage points).
+    #[structopt(long, default_value = "p99=10")]
+    max_regression: Vec<String>,
+
+    /// Highest p-value that counts as a significant change from the baseline
+    #[structopt(long, default_value = "0.05")]
+    significance: f64,
+
+    /// Show a live dashboard instead of the progress line. Up and down change the rate (or
+    /// the worker count, with --concurrency), space pauses, q ends the run early.
+    #[cfg(feature = "dashboard")]
+    #[structopt(long)]
+    dashboard: bool,
+}
+
+/// The request every worker repeats, from the command line
+fn request(args: &Args) -> io::Result<HttpRequest> {
+    let mut request = HttpRequest::new(&args.method, &args.target)?
+        .with_timeout(Duration::from_millis(args.timeout))
+        .with_keep_alive(!args.no_keep_alive);
+
+    for header in args.headers.iter() {
+        let (name, value) = match header.find(':') {
+            Some(colon) => (&header[..colon], header[colon + 1..].trim()),
+            None => {
+                let message = format!("header should look like `Name: value`: {}", header);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+        };
+        request = request.with_header(name.trim(), value);
+    }
+
+    if let Some(body) = args.body.as_ref() {
+        request = request.with_body(body.clone().into_bytes());
+    }
+
+    Ok(request.with_checks(checks(args)?))
+}
+
+/// What a response has to get right to count as a success, from the command line
+fn checks(args: &Args) -> io::Result<Checks> {
+    let (low, high) = match args.expect_status.find('-') {
+        Some(dash) => (&args.expect_status[..dash], &args.expect_status[dash + 1..]),
+        None => (args.expect_status.as_str(), args.expect_status.as_str()),
+    };
+    let (low, high) = match (low.trim().parse(), high.trim().parse()) {
+        (Ok(low), Ok(high)) => (low, high),
+        _ => {
+            let message = format!("status should look like `200-299`: {}", args.expect_status);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+    };
+
+    let mut checks = Checks::new().with("status", Check::Status(low, high));
+    if let Some(text) = args.expect_body.as_ref() {
+        checks = checks.with("body", Check::BodyContains(text.clone()));
+    }
+    if let Some(size) = args.max_body_size {
+        checks = checks.with("size", Check::MaxBodySize(size));
+    }
+    if let Some(ms) = args.max_latency {
+        checks = checks.with("latency", Check::MaxLatency(Duration::from_millis(ms)));
+    }
+
+    Ok(checks)
+}
+
+/// The report to compare against and how, from the command line
+fn baseline(args: &Args) -> io::Result<Option<(RunReport, Thresholds)>> {
+    let path = match args.baseline.as_ref() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let report = RunReport::from_json(&fs::read_to_string(path)?)?;
+
+    let mut thresholds = Thresholds::new().with_significance(args.significance);
+    for limit in args.max_regression.iter() {
+        let (metric, percent) = match limit.find('=') {
+            Some(equals) => (&limit[..equals], limit[equals + 1..].trim().parse::<f64>()),
+            None => (limit.as_str(), "".parse()),
+        };
+        let percent = percent.map_err(|_| {
+            let message = format!("regression limit should look like `p99=10`: {}", limit);
+            io::Error::new(io::ErrorKind::InvalidInput, message)
+        })?;
+        thresholds = thresholds.with(metric.trim().parse::<Metric>()?, percent / 100.0);
+    }
+
+    Ok(Some((report, thresholds)))
+}
+
+fn main() {
+    let args = Args::from_args();
+    let target = match request(&args) {
+        Ok(target) => target,
+        Err(err) => {
+            eprintln!("clobber: {}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let checks = checks(&args).expect("checked along with the request");
+    let baseline = match baseline(&args) {
+        Ok(baseline) => baseline,
+        Err(err) => {
+            eprintln!("clobber: baseline: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let outputs = [
+        (args.json.clone(), RunReport::to_json as fn(&RunReport) -> String),
+        (args.csv.clone(), RunReport::to_csv),
+        (args.totals_csv.clone(), RunReport::totals_csv),
+    ];
+
+    let (totals, report) = task::block_on(async {
+        let (send, recv) = channel(1024);
+        let workers = args.concurrency.unwrap_or(1).max(1);
+        let mut pool = WorkerPool::new(http_worker, send, workers);
+
+        // every job runs until it's stopped, so one task per possible worker
+        for _ in 0..args.max_workers.max(workers) {
+            pool.push(target.clone());
+        }
+
+        let commands = pool.command_channel();
+        let monitor = task::spawn(monitor(args, checks, commands, recv, pool.metrics_handle()));
+        pool.work().await;
+        drop(pool);
+
+        monitor.await
+    });
+
+    eprintln!();
+    println!("{}", totals);
+
+    for (path, format) in outputs.iter() {
+        if let Some(path) = path {
+            write_report(path, &format(&report));
+        }
+    }
+
+    if let Some((baseline, thresholds)) = baseline {
+        let comparison = thresholds.compare(&baseline, &report);
+        println!("\n{}", comparison);
+        if !comparison.passed() {
+            std::process::exit(1);
+        }
+    }
+}
+
+fn write_report(path: &Path, contents: &str) {
+    if let Err(err) = fs::write(path, contents) {
+        eprintln!("clobber: couldn't write {}: {}", path.display(), err);
+    }
+}
+
+/// Everything the run measured
+struct Totals {
+    elapsed: Duration,
+    latency: Histogram,
+    statuses: BTreeMap<u16, u64>,
+    checks: CheckSummary,
+}
+
+impl Totals {
+    fn new(checks: &Checks) -> Self {
+        Self {
+            elapsed: Duration::from_secs(0),
+            latency: Histogram::new(),
+            statuses: BTreeMap::new(),
+            checks: CheckSummary::new(checks),
+        }
+    }
+
+    fn add(&mut self, response: HttpResponse) {
+        self.latency.record(response.latency);
+        if let Some(status) = response.status {
+            *self.statuses.entry(status).or_default() += 1;
+        }
+        self.checks.add(&response);
+    }
+
+    fn errors(&self) -> u64 {
+        self.checks.total() - self.checks.successes
+    }
+}
+
+impl std::fmt::Display for Totals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.elapsed.as_secs_f32();
+        let count = self.latency.count();
+        let rate = match secs {
+            secs if secs > 0.0 => count as f32 / secs,
+            _ => 0.0,
+        };
+
+        writeln!(f, "requests   {} in {:.1}s, {:.1}/s", count, secs, rate)?;
+        writeln!(f, "latency    mean {:?}, {}", self.latency.mean(), self.latency.percentiles())?;
+        for (status, n) in self.statuses.iter() {
+            writeln!(f, "status     {}: {}", status, n)?;
+        }
+        write!(f, "outcomes   {}", self.checks)
+    }
+}
+
+/// Reads results, steers the pool, and prints progress until the run is over
+async fn monitor(
+    args: Args,
+    checks: Checks,
+    commands: Sender<WorkerPoolCommand>,
+    results: Receiver<HttpResponse>,
+    metrics: MetricsHandle,
+) -> (Totals, RunReport) {
+    let start = Instant::now();
+    let duration = Duration::from_secs_f32(args.duration.max(0.0));
+    let tick = Duration::from_millis(args.tick.max(1));
+
+    let mut totals = Totals::new(&checks);
+    let mut report = ReportBuilder::new();
+    let mut pid = PidController::new((args.kp, args.ki, args.kd));
+    let mut steering = Steering {
+        goal: args.rate,
+        workers: args.concurrency.unwrap_or(1).max(1) as f32,
+        paused: false,
+    };
+    #[cfg(feature = "dashboard")]
+    let mut dashboard = match args.dashboard {
+        true => Some(Dashboard::new(60)),
+        false => None,
+    };
+
+    // the controller finds enough workers to reach the rate, the limiter keeps them from
+    // overshooting it
+    commands.send(WorkerPoolCommand::SetRateLimit(args.rate)).ok();
+
+    while start.elapsed() < duration {
+        let tick_start = Instant::now();
+        let mut window = Histogram::new();
+        let mut failures = 0;
+
+        while let Some(remaining) = tick.checked_sub(tick_start.elapsed()) {
+            match future::timeout(remaining, results.recv()).await {
+                Ok(Ok(response)) => {
+                    window.record(response.latency);
+                    if response.failed() {
+                        failures += 1;
+                    }
+                    record(&mut report, &checks, &response);
+                    totals.add(response);
+                }
+                Ok(Err(_)) => {
+                    totals.elapsed = start.elapsed();
+                    return (totals, report.finish());
+                }
+                Err(_) => break,
+            }
+        }
+
+        let rate = window.count() as f32 / tick_start.elapsed().as_secs_f32();
+        let error_rate = match window.count() {
+            0 => 0.0,
+            count => failures as f32 / count as f32,
+        };
+
+        let workers = steering.workers;
+        if let (Some(goal), false) = (steering.goal, steering.paused) {
+            steering.workers = match args.max_error_rate {
+                // too many failures, so back off by the excess instead of chasing the rate
+                Some(max) if error_rate > max => (workers * (1.0 - (error_rate - max))).max(1.0),
+                _ => {
+                    pid.update(goal, rate);
+                    (workers + pid.output()).max(1.0).min(args.max_workers as f32)
+                }
+            };
+            commands.send(WorkerPoolCommand::SetWorkerCount(steering.workers as usize)).ok();
+        }
+        report.tick(&metrics.snapshot(), steering.goal, steering.goal.map(|_| &pid));
+
+        #[cfg(feature = "dashboard")]
+        {
+            if let Some(board) = dashboard.as_mut() {
+                let tick = report.last_tick().expect("just ticked");
+                match board.draw(tick).and_then(|_| board.poll()) {
+                    Ok(inputs) => match steering.steer(inputs, args.max_workers) {
+                        Some(steered) => {
+                            for command in steered {
+                                commands.send(command).ok();
+                            }
+                            continue;
+                        }
+                        None => break,
+                    },
+                    Err(err) => {
+                        // dropping the dashboard gives the terminal back before saying why
+                        dashboard = None;
+                        eprintln!("clobber: dashboard failed, showing progress instead: {}", err);
+                        // nothing's left to resume a paused run
+                        if steering.paused {
+                            steering.paused = false;
+                            commands.send(WorkerPoolCommand::SetRateLimit(steering.goal)).ok();
+                        }
+                    }
+                }
+            }
+        }
+
+        let goal = steering.goal.map_or(String::new(), |goal| format!("/{}", goal));
+        eprint!(
+            "\r{:>6.1}s  {:>8.1}{}/s  workers {:>4}  p99 {:>10?}  errors {}   ",
+            start.elapsed().as_secs_f32(),
+            rate,
+            goal,
+            metrics.snapshot().cur_workers,
+            window.percentile(99.0),
+            totals.errors(),
+        );
+        std::io::stderr().flush().ok();
+    }
+
+    totals.elapsed = start.elapsed();
+    report.stop();
+
+    // let in-flight requests finish and count them, but not the time spent waiting on them.
+    // Paused workers are waiting on the limiter, so let them through to notice the drain.
+    if steering.paused {
+        commands.send(WorkerPoolCommand::SetRateLimit(steering.goal)).ok();
+    }
+    commands.send(WorkerPoolCommand::Drain).ok();
+    while let Ok(response) = results.recv().await {
+        record(&mut report, &checks, &response);
+        totals.add(response);
+    }
+
+    (totals, report.finish())
+}
+
+/// What the run is aiming for. The controller moves the worker count towards the goal; the
+/// dashboard can move the goal, or the worker count when there isn't one, and pause the run.
+struct Steering {
+    goal: Option<f32>,
+    workers: f32,
+    paused: bool,
+}
+
+#[cfg(feature = "dashboard")]
+impl Steering {
+    /// Applies keys pressed on the dashboard. Returns the commands that tell the pool, or
+    /// `None` to end the run.
+    fn steer(
+        &mut self,
+        inputs: Vec<DashboardInput>,
+        max_workers: usize,
+    ) -> Option<Vec<WorkerPoolCommand>> {
+        if inputs.is_empty() {
+            return Some(vec![]);
+        }
+
+        for input in inputs {
+            match (input, self.goal) {
+                (DashboardInput::Raise, Some(goal)) => self.goal = Some(goal * 1.1),
+                (DashboardInput::Lower, Some(goal)) => self.goal = Some(goal / 1.1),
+                (DashboardInput::Raise, None) => {
+                    self.workers = (self.workers + 1.0).min(max_workers as f32)
+                }
+                (DashboardInput::Lower, None) => self.workers = (self.workers - 1.0).max(1.0),
+                (DashboardInput::Pause, _) => self.paused = true,
+                (DashboardInput::Resume, _) => self.paused = false,
+                (DashboardInput::Quit, _) => return None,
+            }
+        }
+
+        // a rate of zero holds every worker at the limiter until it's raised again
+        let rate = match self.paused {
+            true => Some(0.0),
+            false => self.goal,
+        };
+        let mut commands = vec![WorkerPoolCommand::SetRateLimit(rate)];
+        if self.goal.is_none() {
+            commands.push(WorkerPoolCommand::SetWorkerCount(self.workers as usize));
+        }
+
+        Some(commands)
+    }
+}
+
+fn record(report: &mut ReportBuilder, checks: &Checks, response: &HttpResponse) {
+    match checks.category(response) {
+        Some(category) => report.record_failure(response, category),
+        None => report.record(response),
+    }
+}
@@ -0,0 +1,294 @@
+use async_std::sync::Receiver;
+use crossbeam_channel::Sender;
+use log::debug;
+use std::time::Duration;
+
+use crate::{
+    histogram::Percentiles,
+    pool::WorkerPoolCommand,
+    sample::{collect, Sample},
+};
+
+/// How the worker count grows from one plateau to the next
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ramp {
+    /// Add this many workers each plateau
+    Linear(usize),
+    /// Multiply the worker count by this each plateau
+    Geometric(f32),
+}
+
+impl Ramp {
+    fn next(self, workers: usize) -> usize {
+        let next = match self {
+            Ramp::Linear(step) => workers + step,
+            Ramp::Geometric(factor) => (workers as f32 * factor).ceil() as usize,
+        };
+
+        next.max(workers + 1)
+    }
+}
+
+/// Throughput and latency measured while the pool held one worker count
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Plateau {
+    pub workers: usize,
+    /// Results per second
+    pub throughput: f32,
+    pub latency: Percentiles,
+}
+
+/// What a `CapacitySearch` found
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapacityReport {
+    /// Worker count with the highest throughput before latency started to climb
+    pub best: usize,
+    /// Worker count where adding workers stopped adding throughput and started adding latency
+    pub knee: Option<usize>,
+    /// Every plateau measured, in order
+    pub curve: Vec<Plateau>,
+}
+
+impl CapacityReport {
+    /// The plateau measured at the best worker count
+    pub fn best_plateau(&self) -> Option<&Plateau> {
+        self.curve.iter().find(|p| p.workers == self.best)
+    }
+}
+
+/// # CapacitySearch
+///
+/// Answers "how many workers?" by trying them.
+///
+/// The search steps a running `WorkerPool` through increasing worker counts. At each plateau
+/// it lets the pool settle, then measures throughput and tail latency from the pool's output.
+/// It stops at the knee of the curve, where going up a step gains less than `min_gain` in
+/// throughput while the watched latency percentile grows by more than `max_latency_growth`, or
+/// when it reaches `max_workers`. The pool is stopped when the search is done.
+///
+/// The search needs the pool's command channel and the receiving end of its output channel,
+/// so it runs alongside `work`:
+/// ```no_run
+/// # use async_std::{sync::channel, task};
+/// # use clobber::{CapacitySearch, Job, JobStatus, WorkerPool};
+/// # use std::time::Duration;
+/// # async fn request(job: Job<(), Duration>) -> JobStatus { JobStatus::Done }
+/// # task::block_on(async {
+/// let (send, recv) = channel(1024);
+/// let mut pool = WorkerPool::new(request, send, 1);
+/// for _ in 0..512 {
+///     pool.push(());
+/// }
+///
+/// let search = task::spawn(CapacitySearch::new().run(pool.command_channel(), recv));
+/// pool.work().await;
+///
+/// let report = search.await;
+/// println!("best: {} workers", report.best);
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct CapacitySearch {
+    start: usize,
+    max_workers: usize,
+    ramp: Ramp,
+    settle: Duration,
+    plateau: Duration,
+    percentile: f64,
+    min_gain: f32,
+    max_latency_growth: f32,
+}
+
+impl CapacitySearch {
+    pub fn new() -> Self {
+        Self {
+            start: 1,
+            max_workers: 1024,
+            ramp: Ramp::Geometric(2.0),
+            settle: Duration::from_secs(2),
+            plateau: Duration::from_secs(10),
+            percentile: 99.0,
+            min_gain: 0.05,
+            max_latency_growth: 0.2,
+        }
+    }
+
+    /// Range of worker counts to search
+    pub fn with_workers(mut self, start: usize, max: usize) -> Self {
+        self.start = start.max(1);
+        self.max_workers = max.max(self.start);
+        self
+    }
+
+    pub fn with_ramp(mut self, ramp: Ramp) -> Self {
+        self.ramp = ramp;
+        self
+    }
+
+    /// How long to let each plateau settle before measuring, and how long to measure for
+    pub fn with_timing(mut self, settle: Duration, plateau: Duration) -> Self {
+        self.settle = settle;
+        self.plateau = plateau;
+        self
+    }
+
+    /// The knee is where a step gains less than `min_gain` throughput (0.05 is 5%) while the
+    /// latency `percentile` grows by more than `max_latency_growth`
+    pub fn with_knee(mut self, percentile: f64, min_gain: f32, max_latency_growth: f32) -> Self {
+        self.percentile = percentile;
+        self.min_gain = min_gain;
+        self.max_latency_growth = max_latency_growth;
+        self
+    }
+
+    /// Runs the search against a pool, then stops the pool
+    pub async fn run<Out: Sample>(
+        self,
+        commands: Sender<WorkerPoolCommand>,
+        results: Receiver<Out>,
+    ) -> CapacityReport {
+        let mut curve: Vec<Plateau> = vec![];
+        let mut knee = None;
+        let mut workers = self.start;
+
+        loop {
+            if commands.send(WorkerPoolCommand::SetWorkerCount(workers)).is_err() {
+                break;
+            }
+
+            collect(&results, self.settle).await;
+            let collected = collect(&results, self.plateau).await;
+            let open = collected.open;
+
+            let plateau = Plateau {
+                workers,
+                throughput: collected.results as f32 / self.plateau.as_secs_f32(),
+                latency: collected.latency.percentiles(),
+            };
+            debug!("CapacitySearch, {}, {}, {}", workers, plateau.throughput, plateau.latency);
+
+            let at_knee = match curve.last() {
+                Some(last) => self.is_knee(last, &plateau),
+                None => false,
+            };
+            curve.push(plateau);
+
+            if at_knee {
+                knee = Some(workers);
+                break;
+            }
+
+            if !open || workers >= self.max_workers {
+                break;
+            }
+
+            workers = self.ramp.next(workers).min(self.max_workers);
+        }
+
+        commands.send(WorkerPoolCommand::Stop).ok();
+
+        // the knee itself is past the point of diminishing returns
+        let candidates = match knee {
+            Some(_) => &curve[..curve.len() - 1],
+            None => &curve[..],
+        };
+        let best = candidates
+            .iter()
+            .fold(None, |best: Option<&Plateau>, p| match best {
+                Some(best) if best.throughput >= p.throughput => Some(best),
+                _ => Some(p),
+            })
+            .map_or(self.start, |p| p.workers);
+
+        CapacityReport { best, knee, curve }
+    }
+
+    fn is_knee(&self, last: &Plateau, next: &Plateau) -> bool {
+        let gain = relative_change(last.throughput, next.throughput);
+        let growth = relative_change(
+            self.percentile_of(&last.latency).as_secs_f32(),
+            self.percentile_of(&next.latency).as_secs_f32(),
+        );
+
+        gain < self.min_gain && growth > self.max_latency_growth
+    }
+
+    /// The measured percentile closest to the one we're watching
+    fn percentile_of(&self, latency: &Percentiles) -> Duration {
+        match self.percentile {
+            p if p <= 50.0 => latency.p50,
+            p if p <= 90.0 => latency.p90,
+            p if p <= 99.0 => latency.p99,
+            p if p < 100.0 => latency.p999,
+            _ => latency.max,
+        }
+    }
+}
+
+impl Default for CapacitySearch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn relative_change(from: f32, to: f32) -> f32 {
+    if from <= 0.0 {
+        return match to > 0.0 {
+            true => f32::INFINITY,
+            false => 0.0,
+        };
+    }
+
+    (to - from) / from
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Job, JobStatus, WorkerPool};
+    use async_std::{sync::channel, task};
+    use futures_await_test::async_test;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Pretends to be a server that handles 4 requests at once in 20ms, and queues the rest
+    async fn request(job: Job<(), Duration>) -> JobStatus {
+        loop {
+            if job.stop_requested() {
+                return JobStatus::Stopped;
+            }
+
+            let in_flight = IN_FLIGHT.fetch_add(1, Ordering::SeqCst) + 1;
+            let latency = Duration::from_millis(20) * in_flight.max(4) as u32 / 4;
+            task::sleep(latency).await;
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+
+            job.results.send(latency).await;
+        }
+    }
+
+    #[async_test]
+    async fn finds_the_knee() {
+        let (send, recv) = channel(1024);
+        let mut pool = WorkerPool::new(request, send, 1);
+        for _ in 0..32 {
+            pool.push(());
+        }
+
+        let search = CapacitySearch::new()
+            .with_workers(1, 32)
+            .with_ramp(Ramp::Geometric(2.0))
+            .with_timing(Duration::from_millis(50), Duration::from_millis(250))
+            .with_knee(99.0, 0.1, 0.2);
+        let search = task::spawn(search.run(pool.command_channel(), recv));
+
+        pool.work().await;
+        let report = search.await;
+
+        assert_eq!(report.knee, Some(8));
+        assert_eq!(report.best, 4);
+        assert_eq!(report.curve.iter().map(|p| p.workers).collect::<Vec<_>>(), vec![1, 2, 4, 8]);
+        assert!(report.best_plateau().unwrap().throughput > 150.0);
+    }
+}
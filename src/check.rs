@@ -0,0 +1,260 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+#[cfg(feature = "regex")]
+use regex::bytes::Regex;
+
+use crate::http::{HttpError, HttpResponse};
+
+/// One thing a response has to get right to count as a success
+#[derive(Debug, Clone)]
+pub enum Check {
+    /// Status within the range, inclusive, e.g. `Status(200, 299)`
+    Status(u16, u16),
+    /// Body contains this text
+    BodyContains(String),
+    /// Body matches this regex. Needs the `regex` feature.
+    #[cfg(feature = "regex")]
+    BodyMatches(Regex),
+    /// Body no bigger than this many bytes
+    MaxBodySize(usize),
+    MaxLatency(Duration),
+}
+
+impl Check {
+    fn passes(&self, response: &HttpResponse, body: &[u8]) -> bool {
+        match self {
+            Check::Status(low, high) => match response.status {
+                Some(status) => *low <= status && status <= *high,
+                None => false,
+            },
+            Check::BodyContains(text) => {
+                let text = text.as_bytes();
+                text.is_empty() || body.windows(text.len()).any(|window| window == text)
+            }
+            #[cfg(feature = "regex")]
+            Check::BodyMatches(regex) => regex.is_match(body),
+            Check::MaxBodySize(size) => response.body_size <= *size,
+            Check::MaxLatency(latency) => response.latency <= *latency,
+        }
+    }
+
+    fn reads_body(&self) -> bool {
+        match self {
+            Check::BodyContains(_) => true,
+            #[cfg(feature = "regex")]
+            Check::BodyMatches(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Most of a body kept for checking, by default
+const BODY_LIMIT: usize = 1024 * 1024;
+
+/// # Checks
+///
+/// Named checks that every response to an `HttpRequest` has to pass, set with
+/// `HttpRequest::with_checks`.
+///
+/// `http_worker` runs the checks in order as each response comes in, and the first one to fail
+/// goes on the response as `failed_check`. That, or the transport error if the request never
+/// got a response, is the response's failure category; `CheckSummary` counts them up.
+///
+/// Body checks only see the first megabyte of a body unless told otherwise with
+/// `with_body_limit`. Bodies aren't kept at all if no check needs them.
+///
+/// ```
+/// use clobber::{Check, Checks, HttpRequest};
+/// use std::time::Duration;
+///
+/// let checks = Checks::new()
+///     .with("status", Check::Status(200, 299))
+///     .with("in stock", Check::BodyContains("\"available\": true".into()))
+///     .with("slow", Check::MaxLatency(Duration::from_millis(500)));
+///
+/// let request = HttpRequest::get("http://localhost:8000/items/1").unwrap().with_checks(checks);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Checks {
+    checks: Vec<(String, Check)>,
+    body_limit: usize,
+}
+
+impl Checks {
+    pub fn new() -> Self {
+        Self { checks: vec![], body_limit: BODY_LIMIT }
+    }
+
+    /// Adds a check. `name` is the failure category for responses that fail it.
+    pub fn with(mut self, name: &str, check: Check) -> Self {
+        self.checks.push((name.to_string(), check));
+        self
+    }
+
+    /// How much of each body to keep for body checks
+    pub fn with_body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.checks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    /// Name of the check at `index`
+    pub fn name(&self, index: usize) -> Option<&str> {
+        self.checks.get(index).map(|(name, _)| name.as_str())
+    }
+
+    /// Index of the first check `response` fails, given (the start of) its body
+    pub fn verify(&self, response: &HttpResponse, body: &[u8]) -> Option<usize> {
+        self.checks.iter().position(|(_, check)| !check.passes(response, body))
+    }
+
+    /// How much body `http_worker` needs to keep for these checks
+    pub(crate) fn body_limit(&self) -> usize {
+        match self.checks.iter().any(|(_, check)| check.reads_body()) {
+            true => self.body_limit,
+            false => 0,
+        }
+    }
+
+    /// The failure category of a response, or `None` if it succeeded
+    pub fn category<'a>(&'a self, response: &HttpResponse) -> Option<&'a str> {
+        if let Some(error) = response.error {
+            return Some(error_category(error));
+        }
+
+        let check = response.failed_check?;
+        Some(self.name(check).unwrap_or("check"))
+    }
+}
+
+impl Default for Checks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn error_category(error: HttpError) -> &'static str {
+    match error {
+        HttpError::Connect(_) => "connect",
+        HttpError::Write(_) => "write",
+        HttpError::Read(_) => "read",
+        HttpError::Closed => "closed",
+        HttpError::Malformed => "malformed",
+        HttpError::TimedOut => "timeout",
+    }
+}
+
+/// # CheckSummary
+///
+/// Counts responses by failure category, using the same `Checks` the requests were made with.
+#[derive(Debug, Clone)]
+pub struct CheckSummary {
+    checks: Checks,
+    pub successes: u64,
+    /// Responses per failure category
+    pub failures: BTreeMap<String, u64>,
+}
+
+impl CheckSummary {
+    pub fn new(checks: &Checks) -> Self {
+        Self { checks: checks.clone(), successes: 0, failures: BTreeMap::new() }
+    }
+
+    pub fn add(&mut self, response: &HttpResponse) {
+        match self.checks.category(response) {
+            Some(category) => match self.failures.get_mut(category) {
+                Some(count) => *count += 1,
+                None => {
+                    self.failures.insert(category.to_string(), 1);
+                }
+            },
+            None => self.successes += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.successes + self.failures.values().sum::<u64>()
+    }
+
+    /// Fraction of responses that failed, from 0 to 1
+    pub fn error_rate(&self) -> f32 {
+        match self.total() {
+            0 => 0.0,
+            total => (total - self.successes) as f32 / total as f32,
+        }
+    }
+}
+
+impl Display for CheckSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "ok {}", self.successes)?;
+        for (category, count) in self.failures.iter() {
+            write!(f, ", {} {}", category, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn response(status: u16, body_size: usize, latency: u64) -> HttpResponse {
+        HttpResponse {
+            started: Instant::now(),
+            connect: None,
+            latency: Duration::from_millis(latency),
+            status: Some(status),
+            body_size,
+            error: None,
+            failed_check: None,
+            endpoint: 0,
+        }
+    }
+
+    #[test]
+    fn classify() {
+        let checks = Checks::new()
+            .with("status", Check::Status(200, 299))
+            .with("greeting", Check::BodyContains("hello".into()))
+            .with("size", Check::MaxBodySize(10))
+            .with("slow", Check::MaxLatency(Duration::from_millis(100)));
+        assert_eq!(checks.body_limit(), BODY_LIMIT);
+
+        assert_eq!(checks.verify(&response(200, 5, 10), b"hello"), None);
+        assert_eq!(checks.verify(&response(503, 5, 10), b"hello"), Some(0));
+        assert_eq!(checks.verify(&response(200, 7, 10), b"goodbye"), Some(1));
+        assert_eq!(checks.verify(&response(200, 50, 10), b"hello"), Some(2));
+        assert_eq!(checks.verify(&response(200, 5, 200), b"hello"), Some(3));
+
+        let mut summary = CheckSummary::new(&checks);
+        let mut failed = response(200, 50, 10);
+        failed.failed_check = Some(2);
+        let mut errored = response(0, 0, 10);
+        errored.status = None;
+        errored.error = Some(HttpError::TimedOut);
+
+        for response in [response(200, 5, 10), failed, failed, errored].iter() {
+            summary.add(response);
+        }
+        assert_eq!(summary.total(), 4);
+        assert_eq!(summary.error_rate(), 0.75);
+        assert_eq!(summary.to_string(), "ok 1, size 2, timeout 1");
+
+        let status_only = Checks::new().with("status", Check::Status(200, 399));
+        assert_eq!(status_only.body_limit(), 0);
+    }
+}
@@ -0,0 +1,446 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{self, ErrorKind},
+    str::FromStr,
+};
+
+use crate::report::{ReportTick, RunReport};
+
+/// Something a `RunReport` measured that can get worse between runs
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Metric {
+    Throughput,
+    Mean,
+    P50,
+    P90,
+    P99,
+    P999,
+    Max,
+    ErrorRate,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 8] = [
+        Metric::Throughput,
+        Metric::Mean,
+        Metric::P50,
+        Metric::P90,
+        Metric::P99,
+        Metric::P999,
+        Metric::Max,
+        Metric::ErrorRate,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Throughput => "throughput",
+            Metric::Mean => "mean",
+            Metric::P50 => "p50",
+            Metric::P90 => "p90",
+            Metric::P99 => "p99",
+            Metric::P999 => "p99.9",
+            Metric::Max => "max",
+            Metric::ErrorRate => "error rate",
+        }
+    }
+
+    fn value(self, report: &RunReport) -> f64 {
+        let latency = &report.latency;
+        match self {
+            Metric::Throughput => report.throughput as f64,
+            Metric::Mean => latency.mean,
+            Metric::P50 => latency.p50,
+            Metric::P90 => latency.p90,
+            Metric::P99 => latency.p99,
+            Metric::P999 => latency.p999,
+            Metric::Max => latency.max,
+            Metric::ErrorRate => report.error_rate as f64,
+        }
+    }
+
+    /// The metric per tick of a report's timeline, for the ones the timeline has. Idle ticks
+    /// count towards throughput, but have no latency or error rate.
+    fn series(self, report: &RunReport) -> Option<Vec<f64>> {
+        let ticks = report.timeline.iter();
+        let busy = |tick: &&ReportTick| tick.results > 0;
+
+        let series = match self {
+            Metric::Throughput => ticks.map(|tick| tick.rate as f64).collect(),
+            Metric::P50 => ticks.filter(busy).map(|tick| tick.p50).collect(),
+            Metric::P99 => ticks.filter(busy).map(|tick| tick.p99).collect(),
+            Metric::ErrorRate => {
+                ticks.filter(busy).map(|tick| tick.failures as f64 / tick.results as f64).collect()
+            }
+            _ => return None,
+        };
+
+        Some(series)
+    }
+
+    fn higher_is_better(self) -> bool {
+        self == Metric::Throughput
+    }
+
+    /// Change from `baseline` to `candidate`: relative, except for the error rate, which is
+    /// already a fraction and changes in absolute terms
+    fn change(self, baseline: f64, candidate: f64) -> f64 {
+        match self {
+            Metric::ErrorRate => candidate - baseline,
+            _ if baseline == 0.0 && candidate == 0.0 => 0.0,
+            _ if baseline == 0.0 => f64::INFINITY,
+            _ => (candidate - baseline) / baseline,
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "throughput" | "rate" => Ok(Metric::Throughput),
+            "mean" => Ok(Metric::Mean),
+            "p50" => Ok(Metric::P50),
+            "p90" => Ok(Metric::P90),
+            "p99" => Ok(Metric::P99),
+            "p999" | "p99.9" => Ok(Metric::P999),
+            "max" => Ok(Metric::Max),
+            "errors" | "error_rate" | "error-rate" => Ok(Metric::ErrorRate),
+            _ => {
+                let message = format!("unknown metric: {}", s);
+                Err(io::Error::new(ErrorKind::InvalidInput, message))
+            }
+        }
+    }
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// How one metric moved between two runs
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MetricDiff {
+    pub metric: Metric,
+    pub baseline: f64,
+    pub candidate: f64,
+    /// Relative change from baseline to candidate, e.g. 0.1 for 10% higher. Absolute for the
+    /// error rate.
+    pub change: f64,
+    /// Chance of a difference at least this big between the runs' timelines if nothing had
+    /// changed. `None` for metrics the timeline doesn't have, or too short a timeline.
+    pub p_value: Option<f64>,
+    /// Most the metric may get worse by, if it's gated
+    pub threshold: Option<f64>,
+    pub regressed: bool,
+}
+
+impl MetricDiff {
+    /// How much worse the candidate is, in the same terms as `change`; negative if it's better
+    pub fn worsening(&self) -> f64 {
+        match self.metric.higher_is_better() {
+            true => -self.change,
+            false => self.change,
+        }
+    }
+}
+
+/// # Comparison
+///
+/// A diff of two runs, metric by metric, and whether the candidate passes. `Display` gives the
+/// table with a verdict at the bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub diffs: Vec<MetricDiff>,
+}
+
+impl Comparison {
+    /// Whether no gated metric regressed
+    pub fn passed(&self) -> bool {
+        self.diffs.iter().all(|diff| !diff.regressed)
+    }
+
+    pub fn regressions(&self) -> impl Iterator<Item = &MetricDiff> {
+        self.diffs.iter().filter(|diff| diff.regressed)
+    }
+
+    pub fn get(&self, metric: Metric) -> Option<&MetricDiff> {
+        self.diffs.iter().find(|diff| diff.metric == metric)
+    }
+}
+
+fn format_value(metric: Metric, value: f64) -> String {
+    match metric {
+        Metric::Throughput => format!("{:.1}/s", value),
+        Metric::ErrorRate => format!("{:.2}%", value * 100.0),
+        _ => format!("{:.3}ms", value),
+    }
+}
+
+fn format_change(metric: Metric, change: f64) -> String {
+    match metric {
+        Metric::ErrorRate => format!("{:+.2}pt", change * 100.0),
+        _ => format!("{:+.1}%", change * 100.0),
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>12} {:>12} {:>9} {:>8} {:>8}",
+            "metric", "baseline", "candidate", "change", "p-value", "limit"
+        )?;
+
+        for diff in self.diffs.iter() {
+            let p_value = diff.p_value.map_or("-".to_string(), |p| format!("{:.3}", p));
+            let limit = match diff.threshold {
+                Some(threshold) => match diff.metric {
+                    Metric::ErrorRate => format!("{}pt", threshold * 100.0),
+                    _ => format!("{}%", threshold * 100.0),
+                },
+                None => "-".to_string(),
+            };
+            write!(
+                f,
+                "{:<12} {:>12} {:>12} {:>9} {:>8} {:>8}",
+                diff.metric,
+                format_value(diff.metric, diff.baseline),
+                format_value(diff.metric, diff.candidate),
+                format_change(diff.metric, diff.change),
+                p_value,
+                limit,
+            )?;
+            match diff.regressed {
+                true => writeln!(f, "  REGRESSED")?,
+                false => writeln!(f)?,
+            }
+        }
+
+        match self.passed() {
+            true => write!(f, "PASS"),
+            false => {
+                let regressed: Vec<&str> = self.regressions().map(|d| d.metric.name()).collect();
+                write!(f, "FAIL: {} regressed", regressed.join(", "))
+            }
+        }
+    }
+}
+
+/// Two-sided p-value of Welch's t-test between two samples, taking the t statistic as normally
+/// distributed. Needs at least two values on each side.
+fn welch_p_value(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+
+    let mean_var = |xs: &[f64]| {
+        let n = xs.len() as f64;
+        let mean = xs.iter().sum::<f64>() / n;
+        let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        (mean, var / n)
+    };
+    let (mean_a, err_a) = mean_var(a);
+    let (mean_b, err_b) = mean_var(b);
+
+    let err = (err_a + err_b).sqrt();
+    if err == 0.0 {
+        return Some(if mean_a == mean_b { 1.0 } else { 0.0 });
+    }
+
+    let z = (mean_a - mean_b).abs() / err;
+    Some(erfc(z / std::f64::consts::SQRT_2))
+}
+
+/// Complementary error function, to within about 1e-7 (Abramowitz and Stegun 7.1.26)
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x);
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    poly * (-x * x).exp()
+}
+
+/// # Thresholds
+///
+/// Decides whether a run regressed against a baseline, from two `RunReport`s.
+///
+/// Each gated metric has a threshold: the most it may get worse by, relative to the baseline
+/// (0.1 for 10%). Throughput gets worse by going down, everything else by going up. The error
+/// rate is the exception to relative thresholds, since it's already a fraction: its threshold
+/// is in absolute terms, so 0.01 lets it rise by a percentage point.
+///
+/// A change past its threshold only counts as a regression if it's also significant: the
+/// timelines of the two runs are compared with Welch's t-test, and the change has to have a
+/// p-value under `significance` (0.05 by default). Run-to-run noise on a short or jittery run
+/// won't fail the gate, but a consistent shift will. Metrics the timeline doesn't track tick by
+/// tick (mean, p90, p99.9, max) are gated on the threshold alone.
+///
+/// ```
+/// use clobber::{Metric, RunReport, Thresholds};
+///
+/// # let report = RunReport {
+/// #     elapsed: 1.0, results: 0, failures: 0, throughput: 0.0, error_rate: 0.0,
+/// #     latency: Default::default(), errors: Default::default(), timeline: vec![],
+/// # };
+/// # let (baseline, candidate) = (report.clone(), report);
+/// let comparison = Thresholds::new()
+///     .with(Metric::P99, 0.1)
+///     .with(Metric::Throughput, 0.05)
+///     .compare(&baseline, &candidate);
+///
+/// println!("{}", comparison);
+/// assert!(comparison.passed());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thresholds {
+    thresholds: Vec<(Metric, f64)>,
+    significance: f64,
+}
+
+impl Thresholds {
+    /// No gated metrics, so every comparison passes until some are added
+    pub fn new() -> Self {
+        Self { thresholds: vec![], significance: 0.05 }
+    }
+
+    /// Gates `metric`: fail if it gets worse by more than `threshold`
+    pub fn with(mut self, metric: Metric, threshold: f64) -> Self {
+        self.thresholds.retain(|(m, _)| *m != metric);
+        self.thresholds.push((metric, threshold));
+        self
+    }
+
+    /// Highest p-value that counts as a real change
+    pub fn with_significance(mut self, significance: f64) -> Self {
+        self.significance = significance;
+        self
+    }
+
+    pub fn threshold(&self, metric: Metric) -> Option<f64> {
+        self.thresholds.iter().find(|(m, _)| *m == metric).map(|(_, t)| *t)
+    }
+
+    /// Diffs every metric, gating the ones with thresholds
+    pub fn compare(&self, baseline: &RunReport, candidate: &RunReport) -> Comparison {
+        let diffs = Metric::ALL
+            .iter()
+            .map(|&metric| {
+                let (before, after) = (metric.value(baseline), metric.value(candidate));
+                let change = metric.change(before, after);
+                let p_value = match (metric.series(baseline), metric.series(candidate)) {
+                    (Some(a), Some(b)) => welch_p_value(&a, &b),
+                    _ => None,
+                };
+                let threshold = self.threshold(metric);
+
+                let mut diff = MetricDiff {
+                    metric,
+                    baseline: before,
+                    candidate: after,
+                    change,
+                    p_value,
+                    threshold,
+                    regressed: false,
+                };
+                let significant = match p_value {
+                    Some(p) => p < self.significance,
+                    None => true,
+                };
+                diff.regressed = match threshold {
+                    Some(threshold) => diff.worsening() > threshold && significant,
+                    None => false,
+                };
+                diff
+            })
+            .collect();
+
+        Comparison { diffs }
+    }
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::LatencySummary;
+
+    /// A report with one tick per entry of `p99s`, at the given rate
+    fn report(rate: f32, p99s: &[f64]) -> RunReport {
+        let timeline: Vec<ReportTick> = p99s
+            .iter()
+            .enumerate()
+            .map(|(i, &p99)| ReportTick {
+                at: i as f64 + 1.0,
+                results: rate as u64,
+                failures: 0,
+                rate,
+                goal: None,
+                workers: 1,
+                target_workers: 1,
+                p50: p99 / 2.0,
+                p99,
+                pid: None,
+            })
+            .collect();
+        let p99 = p99s.iter().sum::<f64>() / p99s.len() as f64;
+
+        RunReport {
+            elapsed: p99s.len() as f64,
+            results: rate as u64 * p99s.len() as u64,
+            failures: 0,
+            throughput: rate,
+            error_rate: 0.0,
+            latency: LatencySummary { p50: p99 / 2.0, p99, max: p99, ..Default::default() },
+            errors: Default::default(),
+            timeline,
+        }
+    }
+
+    #[test]
+    fn gates_on_significant_changes() {
+        let thresholds = Thresholds::new().with(Metric::P99, 0.1);
+        let baseline = report(100.0, &[10.0, 11.0, 10.0, 9.0, 10.0, 11.0, 9.0, 10.0]);
+
+        // consistently 20% slower
+        let slower = report(100.0, &[12.0, 13.0, 12.0, 11.0, 12.0, 13.0, 11.0, 12.0]);
+        let comparison = thresholds.compare(&baseline, &slower);
+        let p99 = comparison.get(Metric::P99).unwrap();
+        assert!((p99.change - 0.2).abs() < 1e-9);
+        assert!(p99.p_value.unwrap() < 0.001, "{:?}", p99);
+        assert!(!comparison.passed());
+        assert!(comparison.to_string().ends_with("FAIL: p99 regressed"), "{}", comparison);
+        // max got worse too, but isn't gated
+        assert!(!comparison.get(Metric::Max).unwrap().regressed);
+
+        // 20% slower on average, but only because of one noisy tick
+        let noisy = report(100.0, &[10.0, 11.0, 10.0, 9.0, 26.0, 11.0, 9.0, 10.0]);
+        assert!(thresholds.compare(&baseline, &noisy).passed());
+
+        // faster is never a regression
+        let faster = report(100.0, &[5.0, 6.0, 5.0, 4.0, 5.0, 6.0, 4.0, 5.0]);
+        assert!(thresholds.compare(&baseline, &faster).passed());
+
+        let throughput = Thresholds::new().with(Metric::Throughput, 0.05);
+        let comparison = throughput.compare(&baseline, &report(80.0, &[10.0; 8]));
+        assert_eq!(comparison.get(Metric::Throughput).unwrap().p_value, Some(0.0));
+        assert!(!comparison.passed());
+    }
+
+    #[test]
+    fn parse_metrics() {
+        assert_eq!("p99".parse::<Metric>().unwrap(), Metric::P99);
+        assert_eq!("p99.9".parse::<Metric>().unwrap(), Metric::P999);
+        assert_eq!("Throughput".parse::<Metric>().unwrap(), Metric::Throughput);
+        assert!("p42".parse::<Metric>().is_err());
+        assert!(erfc(0.0) > 0.999_999 && (erfc(1.0) - 0.157_299_2).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,302 @@
+//! # Dashboard
+//!
+//! A live view of a running load test in the terminal, with the `dashboard` feature: how the
+//! rate is tracking its goal, how many workers the pool has and wants, what each term of the
+//! controller is contributing, and sparklines of latency and errors over the last few ticks.
+//!
+//! It draws `ReportTick`s, so a control loop that's already building a `RunReport` feeds the
+//! dashboard the tick it just recorded. Keys come back from `poll` for the loop to act on.
+//!
+//! ```no_run
+//! use clobber::{dashboard::{Dashboard, DashboardInput}, ReportBuilder};
+//! # use clobber::{PidController, WorkerPool};
+//! # async fn job(_: clobber::Job<(), std::time::Duration>) -> clobber::JobStatus {
+//! #     clobber::JobStatus::Done
+//! # }
+//! # let (send, _recv) = async_std::sync::channel(1);
+//! # let pool = WorkerPool::new(job, send, 1);
+//! # let pid = PidController::new((0.01, 0.0, 0.0));
+//!
+//! let mut dashboard = Dashboard::new(60);
+//! let mut report = ReportBuilder::new();
+//! let mut goal = 1000.0;
+//!
+//! loop {
+//!     // ... record a tick's results and update the controller
+//!     report.tick(&pool.metrics(), Some(goal), Some(&pid));
+//!     dashboard.draw(report.last_tick().unwrap()).unwrap();
+//!
+//!     for input in dashboard.poll().unwrap() {
+//!         match input {
+//!             DashboardInput::Raise => goal *= 1.1,
+//!             DashboardInput::Lower => goal /= 1.1,
+//!             DashboardInput::Quit => return,
+//!             // ... pause or resume the pool
+//!             _ => {}
+//!         }
+//!     }
+//! }
+//! ```
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Stdout, Write},
+    time::Duration,
+};
+
+use crate::report::ReportTick;
+
+/// A key the user pressed, for the control loop to act on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DashboardInput {
+    /// Raise the setpoint
+    Raise,
+    /// Lower the setpoint
+    Lower,
+    /// Hold the run where it is
+    Pause,
+    /// Carry on after a pause
+    Resume,
+    /// End the run
+    Quit,
+}
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One bar per value, scaled from zero to the largest value
+fn sparkline(values: &VecDeque<f64>) -> String {
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    values
+        .iter()
+        .map(|&value| match max > 0.0 {
+            true => BARS[((value / max * 7.0).round() as usize).min(7)],
+            false => BARS[0],
+        })
+        .collect()
+}
+
+/// The last `width` values of something the dashboard draws a sparkline of
+#[derive(Debug, Clone)]
+struct History {
+    values: VecDeque<f64>,
+    width: usize,
+}
+
+impl History {
+    fn new(width: usize) -> Self {
+        Self { values: VecDeque::with_capacity(width), width }
+    }
+
+    fn push(&mut self, value: f64) {
+        if self.values.len() == self.width {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+}
+
+/// # Dashboard
+///
+/// Draws ticks of a run full screen, with sparklines `width` ticks long. The terminal goes into
+/// raw mode on the first `draw` and comes back out when the dashboard is dropped.
+#[derive(Debug)]
+pub struct Dashboard {
+    last: Option<ReportTick>,
+    rate: History,
+    p50: History,
+    p99: History,
+    errors: History,
+    paused: bool,
+    terminal: Option<Stdout>,
+}
+
+impl Dashboard {
+    pub fn new(width: usize) -> Self {
+        let width = width.max(1);
+        Self {
+            last: None,
+            rate: History::new(width),
+            p50: History::new(width),
+            p99: History::new(width),
+            errors: History::new(width),
+            paused: false,
+            terminal: None,
+        }
+    }
+
+    /// Whether the user has paused the run
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Adds a tick to the sparklines and redraws the screen
+    pub fn draw(&mut self, tick: &ReportTick) -> io::Result<()> {
+        self.push(tick);
+        let lines = self.lines();
+
+        let out = match self.terminal.as_mut() {
+            Some(out) => out,
+            None => {
+                let mut out = io::stdout();
+                terminal::enable_raw_mode().map_err(to_io)?;
+                execute!(out, EnterAlternateScreen, Hide).map_err(to_io)?;
+                self.terminal.get_or_insert(out)
+            }
+        };
+
+        // raw mode doesn't return the cursor on newlines, so each line goes to its own row
+        for (row, line) in lines.iter().enumerate() {
+            queue!(out, MoveTo(0, row as u16), Print(line), Clear(ClearType::UntilNewLine))
+                .map_err(to_io)?;
+        }
+        queue!(out, Clear(ClearType::FromCursorDown)).map_err(to_io)?;
+        out.flush()
+    }
+
+    /// Keys pressed since the last poll. Doesn't wait for any.
+    pub fn poll(&mut self) -> io::Result<Vec<DashboardInput>> {
+        let mut inputs = vec![];
+        while event::poll(Duration::from_secs(0)).map_err(to_io)? {
+            if let Event::Key(key) = event::read().map_err(to_io)? {
+                inputs.extend(self.input(key));
+            }
+        }
+
+        Ok(inputs)
+    }
+
+    fn input(&mut self, key: KeyEvent) -> Option<DashboardInput> {
+        match key.code {
+            KeyCode::Up | KeyCode::Char('+') | KeyCode::Char('=') => Some(DashboardInput::Raise),
+            KeyCode::Down | KeyCode::Char('-') => Some(DashboardInput::Lower),
+            KeyCode::Char(' ') | KeyCode::Char('p') => {
+                self.paused = !self.paused;
+                match self.paused {
+                    true => Some(DashboardInput::Pause),
+                    false => Some(DashboardInput::Resume),
+                }
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                Some(DashboardInput::Quit)
+            }
+            KeyCode::Char('q') | KeyCode::Esc => Some(DashboardInput::Quit),
+            _ => None,
+        }
+    }
+
+    fn push(&mut self, tick: &ReportTick) {
+        self.rate.push(tick.rate as f64);
+        self.p50.push(tick.p50);
+        self.p99.push(tick.p99);
+        self.errors.push(match tick.results {
+            0 => 0.0,
+            results => tick.failures as f64 / results as f64,
+        });
+        self.last = Some(*tick);
+    }
+
+    /// The screen, a line at a time
+    fn lines(&self) -> Vec<String> {
+        let tick = match self.last.as_ref() {
+            Some(tick) => tick,
+            None => return vec![],
+        };
+        let state = match self.paused {
+            true => "PAUSED",
+            false => "running",
+        };
+        let goal = tick.goal.map_or("-".to_string(), |goal| format!("{:.1}", goal));
+        let terms = match tick.pid {
+            Some((p, i, d)) => format!("p {:+.3}  i {:+.3}  d {:+.3}", p, i, d),
+            None => "-".to_string(),
+        };
+        let errors = self.errors.values.back().cloned().unwrap_or(0.0);
+
+        vec![
+            format!("clobber  {:.1}s  {}", tick.at, state),
+            String::new(),
+            format!(
+                "rate     {:>10.1}/s  goal {:>8}/s  {}",
+                tick.rate,
+                goal,
+                sparkline(&self.rate.values)
+            ),
+            format!("workers  {:>10}    target {:>6}", tick.workers, tick.target_workers),
+            format!("pid      {}", terms),
+            format!("p50      {:>10.3}ms  {}", tick.p50, sparkline(&self.p50.values)),
+            format!("p99      {:>10.3}ms  {}", tick.p99, sparkline(&self.p99.values)),
+            format!("errors   {:>10.2}%   {}", errors * 100.0, sparkline(&self.errors.values)),
+            String::new(),
+            "up/+ raise goal   down/- lower goal   space/p pause   q quit".to_string(),
+        ]
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        if let Some(out) = self.terminal.as_mut() {
+            execute!(out, Show, LeaveAlternateScreen).ok();
+            terminal::disable_raw_mode().ok();
+        }
+    }
+}
+
+fn to_io(err: crossterm::ErrorKind) -> io::Error {
+    match err {
+        crossterm::ErrorKind::IoError(err) => err,
+        err => io::Error::other(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick(at: f64, rate: f32, p99: f64, failures: u64) -> ReportTick {
+        ReportTick {
+            at,
+            results: 100,
+            failures,
+            rate,
+            goal: Some(100.0),
+            workers: 4,
+            target_workers: 5,
+            p50: p99 / 2.0,
+            p99,
+            pid: Some((0.5, 0.25, -0.125)),
+        }
+    }
+
+    #[test]
+    fn draws_ticks() {
+        let mut dashboard = Dashboard::new(4);
+        for (i, &p99) in [0.0, 10.0, 20.0, 40.0, 80.0].iter().enumerate() {
+            dashboard.push(&tick(i as f64, 100.0, p99, i as u64));
+        }
+
+        let lines = dashboard.lines();
+        assert_eq!(lines[0], "clobber  4.0s  running");
+        assert!(lines[2].ends_with("goal    100.0/s  ████"), "{}", lines[2]);
+        assert!(lines[3].contains("4    target      5"), "{}", lines[3]);
+        assert_eq!(lines[4], "pid      p +0.500  i +0.250  d -0.125");
+        // only the last four ticks, scaled to the biggest
+        assert!(lines[6].ends_with("80.000ms  ▂▃▅█"), "{}", lines[6]);
+        assert!(lines[7].contains("4.00%"), "{}", lines[7]);
+
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        assert_eq!(dashboard.input(key(KeyCode::Up)), Some(DashboardInput::Raise));
+        assert_eq!(dashboard.input(key(KeyCode::Char('p'))), Some(DashboardInput::Pause));
+        assert!(dashboard.lines()[0].ends_with("PAUSED"));
+        assert_eq!(dashboard.input(key(KeyCode::Char(' '))), Some(DashboardInput::Resume));
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert_eq!(dashboard.input(ctrl_c), Some(DashboardInput::Quit));
+        assert_eq!(dashboard.input(key(KeyCode::Char('x'))), None);
+    }
+}
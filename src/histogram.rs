@@ -0,0 +1,264 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+/// Sub-buckets per power of two. 2^7 keeps every recorded value within 1/64 (~1.6%).
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKET_COUNT: usize = 1 << SUB_BUCKET_BITS;
+const SUB_BUCKET_HALF: usize = SUB_BUCKET_COUNT / 2;
+
+/// Largest value tracked exactly, in microseconds (a little over an hour). Anything longer is
+/// counted in the top bucket, though `max` still reports the real value.
+const MAX_TRACKABLE: u64 = (1 << 32) - 1;
+
+/// # Histogram
+///
+/// A log-bucketed latency histogram in the style of HdrHistogram.
+///
+/// Durations are recorded with microsecond resolution into buckets that double in width every
+/// power of two, each split into 64 linear sub-buckets. That keeps the error on any recorded
+/// value under 1.6% from a microsecond up to an hour, in a fixed ~14KB no matter how many
+/// values are recorded.
+///
+/// Histograms all share the same layout, so they can be merged freely: each worker can record
+/// into its own, and they can be added together for a total. `take` empties a histogram and
+/// returns what it had, which is the easy way to get per-interval snapshots.
+///
+/// ```
+/// use clobber::Histogram;
+/// use std::time::Duration;
+///
+/// let mut histogram = Histogram::new();
+/// for ms in 1..=100 {
+///     histogram.record(Duration::from_millis(ms));
+/// }
+///
+/// let p99 = histogram.percentile(99.0);
+/// assert!(p99 >= Duration::from_millis(98) && p99 <= Duration::from_millis(100));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        let len = index_of(MAX_TRACKABLE) + 1;
+        Self { counts: vec![0; len], count: 0, sum: 0, min: u64::MAX, max: 0 }
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        self.record_n(duration, 1);
+    }
+
+    /// Records the same duration `n` times
+    pub fn record_n(&mut self, duration: Duration, n: u64) {
+        if n == 0 {
+            return;
+        }
+
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+        self.counts[index_of(micros.min(MAX_TRACKABLE))] += n;
+        self.count += n;
+        self.sum += micros as u128 * n as u128;
+        self.min = self.min.min(micros);
+        self.max = self.max.max(micros);
+    }
+
+    /// Adds everything recorded in `other` to this histogram
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Empties the histogram, returning what it held
+    pub fn take(&mut self) -> Histogram {
+        std::mem::take(self)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Histogram::new();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn min(&self) -> Duration {
+        match self.count {
+            0 => Duration::from_secs(0),
+            _ => Duration::from_micros(self.min),
+        }
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max)
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::from_secs(0),
+            n => Duration::from_micros((self.sum / n as u128) as u64),
+        }
+    }
+
+    /// The duration that `percentile` percent of recorded values are at or below, for
+    /// `percentile` between 0 and 100.
+    ///
+    /// Reported as the top of the bucket the value landed in, so it never understates latency,
+    /// and never more than the largest value actually recorded.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::from_secs(0);
+        }
+
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * self.count as f64).ceil() as u64;
+        let rank = rank.max(1);
+
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let value = highest_equivalent(index).min(self.max).max(self.min);
+                return Duration::from_micros(value);
+            }
+        }
+
+        self.max()
+    }
+
+    /// The usual set of percentiles for a latency report
+    pub fn percentiles(&self) -> Percentiles {
+        Percentiles {
+            p50: self.percentile(50.0),
+            p90: self.percentile(90.0),
+            p99: self.percentile(99.0),
+            p999: self.percentile(99.9),
+            max: self.max(),
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bucket index for a value in microseconds
+fn index_of(value: u64) -> usize {
+    let bits = 64 - value.leading_zeros();
+    let bucket = bits.saturating_sub(SUB_BUCKET_BITS);
+    let sub_bucket = (value >> bucket) as usize;
+
+    bucket as usize * SUB_BUCKET_HALF + sub_bucket
+}
+
+/// Largest value in microseconds that lands in the bucket at `index`
+fn highest_equivalent(index: usize) -> u64 {
+    if index < SUB_BUCKET_COUNT {
+        return index as u64;
+    }
+
+    let bucket = index / SUB_BUCKET_HALF - 1;
+    let sub_bucket = (index - bucket * SUB_BUCKET_HALF) as u64;
+    ((sub_bucket + 1) << bucket) - 1
+}
+
+/// Latency at the percentiles most reports care about
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+impl Display for Percentiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "p50 {:?}, p90 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+            self.p50, self.p90, self.p99, self.p999, self.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close_to(actual: Duration, expected: Duration) -> bool {
+        let (actual, expected) = (actual.as_secs_f64(), expected.as_secs_f64());
+        (actual - expected).abs() <= expected * 0.02
+    }
+
+    #[test]
+    fn buckets_round_trip() {
+        for &value in &[0, 1, 127, 128, 129, 1000, 65_432, 1_000_000, MAX_TRACKABLE] {
+            let top = highest_equivalent(index_of(value));
+            assert!(top >= value);
+            assert!(top - value <= value / 64, "{} -> {}", value, top);
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut histogram = Histogram::new();
+        for us in 1..=10_000 {
+            histogram.record(Duration::from_micros(us));
+        }
+
+        let p = histogram.percentiles();
+        assert!(close_to(p.p50, Duration::from_micros(5_000)));
+        assert!(close_to(p.p90, Duration::from_micros(9_000)));
+        assert!(close_to(p.p99, Duration::from_micros(9_900)));
+        assert!(close_to(p.p999, Duration::from_micros(9_990)));
+        assert_eq!(p.max, Duration::from_micros(10_000));
+        assert_eq!(histogram.min(), Duration::from_micros(1));
+        assert!(close_to(histogram.mean(), Duration::from_micros(5_000)));
+    }
+
+    #[test]
+    fn merge_and_take() {
+        let mut a = Histogram::new();
+        let mut b = Histogram::new();
+        a.record_n(Duration::from_millis(1), 99);
+        b.record(Duration::from_secs(2));
+
+        a.merge(&b);
+        assert_eq!(a.count(), 100);
+        assert!(close_to(a.percentile(50.0), Duration::from_millis(1)));
+        assert_eq!(a.percentile(100.0), Duration::from_secs(2));
+
+        let interval = a.take();
+        assert_eq!(interval.count(), 100);
+        assert!(a.is_empty());
+        assert_eq!(a.percentile(99.0), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn out_of_range_values() {
+        let mut histogram = Histogram::new();
+        histogram.record(Duration::from_secs(10_000));
+
+        assert_eq!(histogram.max(), Duration::from_secs(10_000));
+        assert_eq!(histogram.percentile(50.0), Duration::from_secs(10_000));
+    }
+}
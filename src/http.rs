@@ -0,0 +1,723 @@
+use async_std::{future, io::prelude::*, net::TcpStream};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::{self, ErrorKind},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    check::Checks,
+    limiter::RateLimiter,
+    pacing::{Pacer, Pacing},
+    pool::{Job, JobStatus},
+    sample::Sample,
+};
+
+/// # HttpRequest
+///
+/// An HTTP/1.1 request for `http_worker` to repeat.
+///
+/// Connections are kept alive between requests by default, so each worker holds one open
+/// connection to the target and the pool's workers act as a connection pool. With pipelining,
+/// a worker writes several requests before reading any of the responses.
+///
+/// Only plain `http://` urls are supported.
+///
+/// ```no_run
+/// # use async_std::{sync::channel, task};
+/// use clobber::{http_worker, HttpRequest, WorkerPool};
+///
+/// let request = HttpRequest::new("POST", "http://localhost:8000/orders")
+///     .unwrap()
+///     .with_header("Content-Type", "application/json")
+///     .with_body(br#"{"item": 1}"#.to_vec())
+///     .with_pipelining(4);
+///
+/// let (send, recv) = channel(1024);
+/// let mut pool = WorkerPool::new(http_worker, send, 16);
+/// for _ in 0..16 {
+///     pool.push(request.clone());
+/// }
+///
+/// task::spawn(async move {
+///     while let Ok(response) = recv.recv().await {
+///         println!("{:?} in {:?}", response.status, response.latency);
+///     }
+/// });
+/// task::block_on(pool.work());
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    addr: String,
+    host: String,
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Arc<Vec<u8>>,
+    keep_alive: bool,
+    pipelining: usize,
+    timeout: Option<Duration>,
+    count: Option<usize>,
+    pub(crate) endpoint: usize,
+    limiter: Option<RateLimiter>,
+    checks: Option<Arc<Checks>>,
+    pacing: Pacing,
+}
+
+impl HttpRequest {
+    pub fn get(url: &str) -> io::Result<Self> {
+        Self::new("GET", url)
+    }
+
+    /// A request with no body. Fails if `url` isn't an `http://` url with a host.
+    pub fn new(method: &str, url: &str) -> io::Result<Self> {
+        let invalid =
+            |reason: &str| io::Error::new(ErrorKind::InvalidInput, format!("{}: {}", reason, url));
+
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => return Err(invalid("only http:// urls are supported")),
+        };
+
+        let (host, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+
+        if host.is_empty() {
+            return Err(invalid("no host"));
+        }
+
+        let addr = match host.contains(':') {
+            true => host.to_string(),
+            false => format!("{}:80", host),
+        };
+
+        Ok(Self {
+            addr,
+            host: host.to_string(),
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: vec![],
+            body: Arc::new(vec![]),
+            keep_alive: true,
+            pipelining: 1,
+            timeout: None,
+            count: None,
+            endpoint: 0,
+            limiter: None,
+            checks: None,
+            pacing: Pacing::None,
+        })
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the body, along with its `Content-Length`
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = Arc::new(body);
+        self
+    }
+
+    /// Whether to keep connections open between requests. On by default.
+    pub fn with_keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// How many requests to write before reading the responses. Needs keep-alive.
+    pub fn with_pipelining(mut self, depth: usize) -> Self {
+        self.pipelining = depth.max(1);
+        self
+    }
+
+    /// Gives up on a request, including connecting, after `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Makes this many requests and finishes, instead of looping until stopped
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// Tags every response to this request with `endpoint`, so a pool running several
+    /// different requests can tell their responses apart
+    pub fn with_endpoint(mut self, endpoint: usize) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// Waits on `limiter` as well as the pool's rate limiter before each request, capping this
+    /// request's rate on its own
+    pub fn with_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Checks every response has to pass. The first one it fails is set as the response's
+    /// `failed_check`.
+    pub fn with_checks(mut self, checks: Checks) -> Self {
+        self.checks = Some(Arc::new(checks));
+        self
+    }
+
+    /// Think time or cycle time between requests, or between batches when pipelining. Only
+    /// matters for requests that are repeated, so not in open-loop pools or a `TrafficMix`.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// The request as it goes on the wire
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", self.method, self.path, self.host);
+
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        let has_length =
+            self.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("content-length"));
+        if !self.body.is_empty() && !has_length {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+
+        if !self.keep_alive {
+            head.push_str("Connection: close\r\n");
+        }
+
+        head.push_str("\r\n");
+
+        let mut encoded = head.into_bytes();
+        encoded.extend_from_slice(&self.body);
+        encoded
+    }
+
+    fn pipelining(&self) -> usize {
+        match self.keep_alive {
+            true => self.pipelining,
+            false => 1,
+        }
+    }
+}
+
+/// Where a request went wrong
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HttpError {
+    Connect(ErrorKind),
+    Write(ErrorKind),
+    Read(ErrorKind),
+    /// The connection closed before the response was complete
+    Closed,
+    /// The response wasn't valid HTTP
+    Malformed,
+    TimedOut,
+}
+
+/// The outcome of one request
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub started: Instant,
+    /// Time spent connecting, if this request opened a new connection
+    pub connect: Option<Duration>,
+    /// Time from starting the request to the end of the response, or to failing
+    pub latency: Duration,
+    /// Response status, if the response got that far
+    pub status: Option<u16>,
+    /// Bytes of response body, not counting headers or chunk framing
+    pub body_size: usize,
+    pub error: Option<HttpError>,
+    /// Index of the first of the request's `Checks` that the response failed
+    pub failed_check: Option<usize>,
+    /// The request's endpoint tag, 0 unless set with `HttpRequest::with_endpoint`
+    pub endpoint: usize,
+}
+
+impl Sample for HttpResponse {
+    fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    fn failed(&self) -> bool {
+        self.error.is_some() || self.failed_check.is_some()
+    }
+}
+
+/// # http_worker
+///
+/// A worker for `WorkerPool` that repeats an `HttpRequest`, sending an `HttpResponse` for
+/// every request.
+///
+/// It runs until stopped, or until it's made the request's `count` of requests. Jobs started
+/// by an open-loop pool make a single request. Each request waits on the pool's rate limiter,
+/// and the request's own if it has one, before it's written.
+pub async fn http_worker(job: Job<HttpRequest, HttpResponse>) -> JobStatus {
+    requests(&job, &mut None).await
+}
+
+/// # pooled_http_worker
+///
+/// `http_worker` for a pool whose worker slots carry `HttpConnections`. A connection left open
+/// by one job is picked up by the next job in the same slot going to the same address, so jobs
+/// that make a single request, like the ones a `TrafficMix` hands out, don't each connect anew.
+pub async fn pooled_http_worker(job: Job<HttpRequest, HttpResponse, HttpConnections>) -> JobStatus {
+    let addr = &job.task.addr;
+    let mut connection = job.context.take(addr);
+    let status = requests(&job, &mut connection).await;
+
+    if let Some(connection) = connection {
+        job.context.keep(addr, connection);
+    }
+    status
+}
+
+/// Makes a job's requests, on `connection` if it's already open
+async fn requests<Ctx>(
+    job: &Job<HttpRequest, HttpResponse, Ctx>,
+    connection: &mut Option<Connection>,
+) -> JobStatus {
+    let request = &job.task;
+    let encoded = request.encode();
+    let head_only = request.method.eq_ignore_ascii_case("HEAD");
+    let body_limit = request.checks.as_ref().map_or(0, |checks| checks.body_limit());
+    let mut remaining = match job.scheduled {
+        Some(_) => Some(1),
+        None => request.count,
+    };
+    let mut pacer = Pacer::new(request.pacing);
+
+    loop {
+        if job.stop_requested() {
+            return JobStatus::Stopped;
+        }
+
+        if remaining != Some(0) && job.sleep(pacer.next_delay()).await {
+            return JobStatus::Stopped;
+        }
+
+        let batch = match remaining {
+            Some(0) => return JobStatus::Done,
+            Some(n) => n.min(request.pipelining()),
+            None => request.pipelining(),
+        };
+        remaining = remaining.map(|n| n - batch);
+
+        for _ in 0..batch {
+            job.limiter.acquire().await;
+            if let Some(limiter) = request.limiter.as_ref() {
+                limiter.acquire().await;
+            }
+        }
+
+        let started = Instant::now();
+        let deadline = request.timeout.map(|timeout| started + timeout);
+        let mut connect = None;
+
+        let sent = within(deadline, async {
+            if connection.is_none() {
+                let stream = TcpStream::connect(&request.addr)
+                    .await
+                    .map_err(|e| HttpError::Connect(e.kind()))?;
+                stream.set_nodelay(true).ok();
+                connect = Some(started.elapsed());
+                *connection = Some(Connection::new(stream));
+            }
+
+            let stream = &mut connection.as_mut().expect("connected above").stream;
+            for _ in 0..batch {
+                stream.write_all(&encoded).await.map_err(|e| HttpError::Write(e.kind()))?;
+            }
+
+            Ok(())
+        })
+        .await;
+
+        // only the first response of a batch pays for connecting
+        let mut response = |status, body_size, error| HttpResponse {
+            started,
+            connect: connect.take(),
+            latency: started.elapsed(),
+            status,
+            body_size,
+            error,
+            failed_check: None,
+            endpoint: request.endpoint,
+        };
+
+        if let Err(error) = sent {
+            *connection = None;
+            for _ in 0..batch {
+                job.results.send(response(None, 0, Some(error))).await;
+            }
+            continue;
+        }
+
+        for read in 0..batch {
+            let conn = connection.as_mut().expect("connected above");
+            match within(deadline, conn.read_response(head_only, body_limit)).await {
+                Ok(head) => {
+                    if head.close {
+                        *connection = None;
+                    }
+
+                    let mut checked = response(Some(head.status), head.body_size, None);
+                    if let Some(checks) = request.checks.as_ref() {
+                        checked.failed_check = checks.verify(&checked, &head.body);
+                    }
+                    job.results.send(checked).await;
+
+                    // the server closed early, so the rest of the batch was never answered
+                    if connection.is_none() && read + 1 < batch {
+                        for _ in read + 1..batch {
+                            job.results.send(response(None, 0, Some(HttpError::Closed))).await;
+                        }
+                        break;
+                    }
+                }
+                Err(error) => {
+                    *connection = None;
+                    for _ in read..batch {
+                        job.results.send(response(None, 0, Some(error))).await;
+                    }
+                    break;
+                }
+            }
+        }
+
+        if !request.keep_alive {
+            *connection = None;
+        }
+    }
+}
+
+/// Runs `future`, failing with `TimedOut` if it's still going at `deadline`
+async fn within<T>(
+    deadline: Option<Instant>,
+    future: impl Future<Output = Result<T, HttpError>>,
+) -> Result<T, HttpError> {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => return future.await,
+    };
+
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    match future::timeout(remaining, future).await {
+        Ok(result) => result,
+        Err(_) => Err(HttpError::TimedOut),
+    }
+}
+
+/// What we need to know from a response
+struct Head {
+    status: u16,
+    body_size: usize,
+    /// The server will close the connection after this response
+    close: bool,
+    /// The start of the body, if we were asked to keep it
+    body: Vec<u8>,
+}
+
+/// # HttpConnections
+///
+/// The connections a worker slot keeps open between jobs, one per address, for a pool running
+/// `pooled_http_worker`. Use `HttpConnections::default` to create a slot's connections and
+/// `drop` to close them.
+///
+/// ```no_run
+/// # use async_std::{sync::channel, task};
+/// use clobber::{pooled_http_worker, HttpConnections, HttpRequest, TrafficMix, WorkerPool};
+///
+/// let mix = TrafficMix::new(7)
+///     .with_endpoint("home", HttpRequest::get("http://localhost:8000/").unwrap(), 3)
+///     .with_endpoint("about", HttpRequest::get("http://localhost:8000/about").unwrap(), 1);
+///
+/// let (send, _recv) = channel(1024);
+/// let mut pool =
+///     WorkerPool::with_context(pooled_http_worker, send, 8, HttpConnections::default, drop)
+///         .with_queue(mix);
+/// task::block_on(pool.work());
+/// ```
+#[derive(Clone, Default)]
+pub struct HttpConnections {
+    open: Arc<Mutex<HashMap<String, Connection>>>,
+}
+
+impl HttpConnections {
+    fn take(&self, addr: &str) -> Option<Connection> {
+        self.lock().remove(addr)
+    }
+
+    fn keep(&self, addr: &str, connection: Connection) {
+        self.lock().insert(addr.to_string(), connection);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Connection>> {
+        self.open.lock().expect("connections lock poisoned")
+    }
+}
+
+/// A connection, plus anything read past the end of the last response
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    /// How much of the body being read to keep
+    keep: usize,
+    body: Vec<u8>,
+}
+
+/// Longest response head we'll accept
+const MAX_HEAD: usize = 64 * 1024;
+
+impl Connection {
+    fn new(stream: TcpStream) -> Self {
+        Self { stream, buffer: vec![], keep: 0, body: vec![] }
+    }
+
+    /// Reads the next response, keeping up to `keep` bytes of its body
+    async fn read_response(&mut self, head_only: bool, keep: usize) -> Result<Head, HttpError> {
+        self.keep = keep;
+        self.body.clear();
+
+        loop {
+            let raw = self.read_until(b"\r\n\r\n", MAX_HEAD).await?;
+            let head = std::str::from_utf8(&raw).map_err(|_| HttpError::Malformed)?;
+            let mut lines = head.split("\r\n");
+
+            // HTTP/1.1 200 OK
+            let status_line = lines.next().unwrap_or_default();
+            let mut parts = status_line.splitn(3, ' ');
+            let version = parts.next().unwrap_or_default();
+            let status: u16 = match parts.next().map(str::parse) {
+                Some(Ok(status)) if version.starts_with("HTTP/") => status,
+                _ => return Err(HttpError::Malformed),
+            };
+
+            // interim responses like 100 Continue come before the real one
+            if (100..200).contains(&status) && status != 101 {
+                continue;
+            }
+
+            let mut length = None;
+            let mut chunked = false;
+            let mut close = version == "HTTP/1.0";
+            for line in lines.filter(|line| !line.is_empty()) {
+                let (name, value) = match line.find(':') {
+                    Some(colon) => (&line[..colon], line[colon + 1..].trim()),
+                    None => return Err(HttpError::Malformed),
+                };
+
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = Some(value.parse::<usize>().map_err(|_| HttpError::Malformed)?);
+                } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                    chunked = value.to_ascii_lowercase().contains("chunked");
+                } else if name.eq_ignore_ascii_case("connection") {
+                    close = value.eq_ignore_ascii_case("close");
+                }
+            }
+
+            let no_body = head_only || status == 204 || status == 304;
+            let body_size = match (no_body, chunked, length) {
+                (true, _, _) => 0,
+                (false, true, _) => self.skip_chunked().await?,
+                (false, false, Some(length)) => self.skip(length).await?,
+                (false, false, None) => {
+                    close = true;
+                    self.skip_to_close().await?
+                }
+            };
+
+            let body = std::mem::take(&mut self.body);
+            return Ok(Head { status, body_size, close, body });
+        }
+    }
+
+    /// Reads more from the stream into the buffer, failing if the connection has closed
+    async fn fill(&mut self) -> Result<usize, HttpError> {
+        let mut chunk = [0; 8192];
+        match self.stream.read(&mut chunk).await {
+            Ok(0) => Err(HttpError::Closed),
+            Ok(read) => {
+                self.buffer.extend_from_slice(&chunk[..read]);
+                Ok(read)
+            }
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => Err(HttpError::Closed),
+            Err(e) => Err(HttpError::Read(e.kind())),
+        }
+    }
+
+    /// Takes everything up to `delimiter` out of the buffer, dropping the delimiter itself
+    async fn read_until(&mut self, delimiter: &[u8], limit: usize) -> Result<Vec<u8>, HttpError> {
+        let mut searched = 0;
+
+        loop {
+            if let Some(at) = find(&self.buffer[searched..], delimiter) {
+                let end = searched + at;
+                let found = self.buffer[..end].to_vec();
+                self.buffer.drain(..end + delimiter.len());
+                return Ok(found);
+            }
+
+            if self.buffer.len() > limit {
+                return Err(HttpError::Malformed);
+            }
+
+            searched = self.buffer.len().saturating_sub(delimiter.len() - 1);
+            self.fill().await?;
+        }
+    }
+
+    /// Discards `n` bytes
+    async fn skip(&mut self, n: usize) -> Result<usize, HttpError> {
+        let mut left = n;
+
+        loop {
+            let take = left.min(self.buffer.len());
+            self.discard(take);
+            left -= take;
+
+            if left == 0 {
+                return Ok(n);
+            }
+
+            self.fill().await?;
+        }
+    }
+
+    /// Discards a chunked body, returning how big it was
+    async fn skip_chunked(&mut self) -> Result<usize, HttpError> {
+        let mut size = 0;
+
+        loop {
+            let line = self.read_until(b"\r\n", MAX_HEAD).await?;
+            let line = std::str::from_utf8(&line).map_err(|_| HttpError::Malformed)?;
+            let hex = line.split(';').next().unwrap_or_default().trim();
+            let chunk = usize::from_str_radix(hex, 16).map_err(|_| HttpError::Malformed)?;
+
+            if chunk == 0 {
+                // trailers, if any, end with an empty line
+                while !self.read_until(b"\r\n", MAX_HEAD).await?.is_empty() {}
+                return Ok(size);
+            }
+
+            self.skip(chunk).await?;
+            if !self.read_until(b"\r\n", 2).await?.is_empty() {
+                return Err(HttpError::Malformed);
+            }
+            size += chunk;
+        }
+    }
+
+    /// Discards everything until the server closes the connection
+    async fn skip_to_close(&mut self) -> Result<usize, HttpError> {
+        let mut size = self.buffer.len();
+        self.discard(self.buffer.len());
+
+        loop {
+            match self.fill().await {
+                Ok(read) => {
+                    size += read;
+                    self.discard(read);
+                }
+                Err(HttpError::Closed) => return Ok(size),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// Drops `n` bytes from the front of the buffer, keeping what's wanted of the body
+    fn discard(&mut self, n: usize) {
+        let keep = n.min(self.keep.saturating_sub(self.body.len()));
+        self.body.extend_from_slice(&self.buffer[..keep]);
+        self.buffer.drain(..n);
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{http_server, run},
+        Check,
+    };
+    use futures_await_test::async_test;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers every request with `response`, counting connections
+    async fn server(response: &'static [u8], connections: Arc<AtomicUsize>) -> String {
+        format!("http://{}/hello", http_server(move |_| response, connections).await)
+    }
+
+    #[test]
+    fn encode() {
+        let request = HttpRequest::new("POST", "http://localhost:8000/a/b?c=d")
+            .unwrap()
+            .with_header("X-Test", "yes")
+            .with_body(b"hello".to_vec())
+            .with_keep_alive(false);
+
+        let expected = "POST /a/b?c=d HTTP/1.1\r\nHost: localhost:8000\r\nX-Test: yes\r\n\
+                        Content-Length: 5\r\nConnection: close\r\n\r\nhello";
+        assert_eq!(String::from_utf8(request.encode()).unwrap(), expected);
+        assert_eq!(HttpRequest::get("http://example.com").unwrap().addr, "example.com:80");
+        assert!(HttpRequest::get("https://example.com").is_err());
+    }
+
+    #[async_test]
+    async fn keep_alive_and_pipelining() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let url = server(response, connections.clone()).await;
+
+        let request = HttpRequest::get(&url).unwrap().with_pipelining(3).with_count(10);
+        let responses = run(http_worker, request, 2).await;
+
+        assert_eq!(responses.len(), 20);
+        assert!(responses.iter().all(|r| r.status == Some(200) && r.body_size == 5));
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[async_test]
+    async fn chunked_and_errors() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let chunked = b"HTTP/1.1 503 Nope\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        4\r\nwait\r\n6;x=y\r\n a bit\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let url = server(chunked, connections.clone()).await;
+
+        let responses = run(http_worker, HttpRequest::get(&url).unwrap().with_count(2), 1).await;
+        assert_eq!(responses.len(), 2);
+        assert!(responses.iter().all(|r| r.status == Some(503) && r.body_size == 10));
+
+        // the body is put back together from its chunks for checking
+        let checks = Checks::new()
+            .with("status", Check::Status(500, 599))
+            .with("body", Check::BodyContains("wait a bit".into()))
+            .with("small", Check::MaxBodySize(4));
+        let responses =
+            run(http_worker, HttpRequest::get(&url).unwrap().with_checks(checks).with_count(1), 1);
+        assert_eq!(responses.await[0].failed_check, Some(2));
+
+        let garbage = server(b"SMTP ready\r\n\r\n", connections.clone()).await;
+        let responses =
+            run(http_worker, HttpRequest::get(&garbage).unwrap().with_count(1), 1).await;
+        assert_eq!(responses[0].error, Some(HttpError::Malformed));
+
+        let silent = server(b"", connections).await;
+        let request = HttpRequest::get(&silent).unwrap().with_count(1);
+        let responses = run(http_worker, request.with_timeout(Duration::from_millis(50)), 1).await;
+        assert_eq!(responses[0].error, Some(HttpError::TimedOut));
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+use crate::histogram::Histogram;
+
+/// # LatencyRecorder
+///
+/// Records request latency the way wrk2 does, correcting for coordinated omission.
+///
+/// Timing a request from when it was actually sent only tells you the *service time*. If the
+/// target stalls, a closed-loop worker stops sending for the length of the stall, and the
+/// requests it would have sent in that time are never measured; the stall shows up as one slow
+/// sample instead of the many that real users would have seen. The *response time* measures
+/// from when the request was meant to be sent, which is what a user waiting on it experiences.
+///
+/// The intended start comes from one of two places:
+///
+/// * the pool's schedule, when running open-loop (`Job::scheduled`), passed to `record`
+/// * the target rate, for closed-loop workers. `record_unscheduled` back-fills the samples that
+///   would have been taken during a stall, in the style of HdrHistogram's
+///   `recordValueWithExpectedInterval`.
+///
+/// Both series are kept in `Histogram`s, so recorders from several workers can be merged.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyRecorder {
+    expected_interval: Option<Duration>,
+    service: Histogram,
+    response: Histogram,
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A recorder for workers that aim to send `rate` requests per second between them
+    pub fn with_rate(rate: f32) -> Self {
+        let mut recorder = Self::new();
+        if rate > 0.0 {
+            recorder.expected_interval = Some(Duration::from_secs_f64(1.0 / rate as f64));
+        }
+
+        recorder
+    }
+
+    /// Records a request that was meant to start at `intended`.
+    ///
+    /// If it was sent early, the response time is the same as the service time.
+    pub fn record(&mut self, intended: Instant, started: Instant, finished: Instant) {
+        let service = finished.saturating_duration_since(started);
+        let response = finished.saturating_duration_since(intended.min(started));
+
+        self.service.record(service);
+        self.response.record(response);
+    }
+
+    /// Records a request with no schedule behind it.
+    ///
+    /// With an expected interval between requests (see `with_rate`), a request that took
+    /// longer than the interval means others should have been sent while it was outstanding.
+    /// Each of those is back-filled into the response times, with the latency it would have
+    /// seen: `service - interval`, `service - 2 * interval`, and so on.
+    pub fn record_unscheduled(&mut self, started: Instant, finished: Instant) {
+        let service = finished.saturating_duration_since(started);
+        self.service.record(service);
+        self.response.record(service);
+
+        let interval = match self.expected_interval {
+            Some(interval) if interval > Duration::from_secs(0) => interval,
+            _ => return,
+        };
+
+        let mut missing = service.checked_sub(interval);
+        while let Some(latency) = missing.filter(|&latency| latency >= interval) {
+            self.response.record(latency);
+            missing = latency.checked_sub(interval);
+        }
+    }
+
+    /// Number of requests actually recorded
+    pub fn count(&self) -> u64 {
+        self.service.count()
+    }
+
+    /// Latency measured from when requests were sent
+    pub fn service_times(&self) -> &Histogram {
+        &self.service
+    }
+
+    /// Latency measured from when requests were meant to be sent, including back-filled
+    /// samples
+    pub fn response_times(&self) -> &Histogram {
+        &self.response
+    }
+
+    /// Adds everything recorded by `other` to this recorder
+    pub fn merge(&mut self, other: &LatencyRecorder) {
+        self.service.merge(&other.service);
+        self.response.merge(&other.response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn scheduled_stall() {
+        let start = Instant::now();
+        let mut recorder = LatencyRecorder::new();
+
+        // three requests meant for 0, 10 and 20ms all went out at 20ms and took 5ms
+        for intended in &[0, 10, 20] {
+            recorder.record(start + ms(*intended), start + ms(20), start + ms(25));
+        }
+
+        assert_eq!(recorder.count(), 3);
+        assert_eq!(recorder.service_times().max(), ms(5));
+        assert_eq!(recorder.response_times().min(), ms(5));
+        assert_eq!(recorder.response_times().max(), ms(25));
+    }
+
+    #[test]
+    fn backfilled_stall() {
+        let start = Instant::now();
+        let mut recorder = LatencyRecorder::with_rate(100.0);
+
+        recorder.record_unscheduled(start, start + ms(5));
+        recorder.record_unscheduled(start, start + ms(55));
+
+        // the 55ms stall hid requests that would have seen 45, 35, 25 and 15ms
+        assert_eq!(recorder.count(), 2);
+        assert_eq!(recorder.response_times().count(), 6);
+        assert_eq!(recorder.response_times().min(), ms(5));
+        assert_eq!(recorder.service_times().max(), ms(55));
+
+        let median = recorder.response_times().percentile(50.0);
+        assert!(median >= ms(25) && median < ms(26));
+    }
+}
@@ -1,11 +1,64 @@
+mod arrivals;
+mod capacity;
+mod check;
+mod compare;
+mod histogram;
+mod http;
+mod latency;
+mod limiter;
+mod metrics;
+mod mix;
+mod observer;
+mod pacing;
 mod pid;
 mod pool;
+mod queue;
+#[cfg(feature = "json")]
+mod replay;
+mod report;
+mod rng;
+mod sample;
+mod scenario;
+mod setpoint;
+mod slo;
+mod tcp;
+mod template;
+#[cfg(test)]
+mod testing;
+mod udp;
 
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
 #[cfg(feature = "tuning")]
 pub mod tuning;
 
+pub use arrivals::Arrivals;
+pub use capacity::{CapacityReport, CapacitySearch, Plateau, Ramp};
+pub use check::{Check, CheckSummary, Checks};
+pub use compare::{Comparison, Metric, MetricDiff, Thresholds};
+pub use histogram::{Histogram, Percentiles};
+pub use http::{
+    http_worker, pooled_http_worker, HttpConnections, HttpError, HttpRequest, HttpResponse,
+};
+pub use latency::LatencyRecorder;
+pub use limiter::RateLimiter;
+pub use metrics::{DurationStats, MetricsHandle, PoolMetrics};
+pub use mix::{EndpointSummary, MixSummary, TrafficMix};
+pub use observer::{LogObserver, PoolEvent, PoolObserver};
+pub use pacing::{Pacer, Pacing};
 pub use pid::PidController;
 pub use pool::{Job, JobStatus, WorkerPool, WorkerPoolCommand};
+pub use queue::{FairQueue, Fifo, Lifo, PriorityQueue, Queued, TaskQueue};
+#[cfg(feature = "json")]
+pub use replay::{LoggedRequest, Pace, RequestLog};
+pub use report::{LatencySummary, ReportBuilder, ReportTick, RunReport};
+pub use sample::Sample;
+pub use scenario::{Control, Scenario, ScenarioReport, Stage, StageSummary};
+pub use setpoint::Setpoint;
+pub use slo::LatencyController;
+pub use tcp::{tcp_worker, ReadUntil, TcpError, TcpRequest, TcpResponse};
+pub use template::{Feeder, PayloadTemplate, RequestTemplate, Template};
+pub use udp::{udp_worker, UdpError, UdpRequest, UdpResponse, UdpSummary};
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,188 @@
+use async_std::task;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// # RateLimiter
+///
+/// An async token bucket shared by every job in a `WorkerPool`.
+///
+/// Tokens refill continuously at `rate` per second, up to `burst` of them. Each call to
+/// `acquire` takes one, waiting for it if the bucket is empty. Combining this with a
+/// concurrency controller gives a hard cap on the rate no matter how many workers the
+/// controller decides to run.
+///
+/// The limiter is a cheap handle; clones share the same bucket. A limiter with no rate never
+/// waits, which is what pools start with.
+///
+/// ```
+/// # use async_std::task;
+/// use clobber::RateLimiter;
+///
+/// let limiter = RateLimiter::new(1000.0, 10);
+/// task::block_on(async {
+///     for _ in 0..20 {
+///         limiter.acquire().await;
+///         // ... make a request
+///     }
+/// });
+/// ```
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+struct Bucket {
+    rate: Option<f32>,
+    burst: f32,
+    tokens: f32,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        if let Some(rate) = self.rate {
+            let earned = now.duration_since(self.last_refill).as_secs_f32() * rate;
+            self.tokens = (self.tokens + earned).min(self.burst);
+        }
+
+        self.last_refill = now;
+    }
+}
+
+/// Longest we'll sleep before checking the bucket again, so rate changes are noticed promptly
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+impl RateLimiter {
+    /// A limiter allowing `rate` acquisitions per second, with up to `burst` at once
+    pub fn new(rate: f32, burst: usize) -> Self {
+        let limiter = Self::unlimited();
+        limiter.set_burst(burst);
+        limiter.set_rate(Some(rate));
+        limiter
+    }
+
+    /// A limiter that never waits
+    pub fn unlimited() -> Self {
+        let bucket = Bucket { rate: None, burst: 1.0, tokens: 1.0, last_refill: Instant::now() };
+        Self { bucket: Arc::new(Mutex::new(bucket)) }
+    }
+
+    /// Current rate in acquisitions per second, or `None` if unlimited
+    pub fn rate(&self) -> Option<f32> {
+        self.lock().rate
+    }
+
+    /// Changes the rate. Takes effect for every holder of the limiter, including jobs that are
+    /// currently waiting. A rate of zero pauses everyone until it's raised again.
+    ///
+    /// Going from unlimited to limited starts with a full bucket.
+    pub fn set_rate(&self, rate: Option<f32>) {
+        let mut bucket = self.lock();
+        bucket.refill();
+
+        if bucket.rate.is_none() {
+            bucket.tokens = bucket.burst;
+        }
+
+        bucket.rate = rate.map(|rate| rate.max(0.0));
+    }
+
+    /// Changes how many tokens can build up while nobody is acquiring them
+    pub fn set_burst(&self, burst: usize) {
+        let mut bucket = self.lock();
+        bucket.burst = burst.max(1) as f32;
+        bucket.tokens = bucket.tokens.min(bucket.burst);
+    }
+
+    /// Takes a token if one is available right now
+    pub fn try_acquire(&self) -> bool {
+        self.try_take().is_none()
+    }
+
+    /// Waits for a token and takes it
+    pub async fn acquire(&self) {
+        while let Some(wait) = self.try_take() {
+            task::sleep(wait).await;
+        }
+    }
+
+    /// Takes a token, or returns roughly how long until one will be available
+    fn try_take(&self) -> Option<Duration> {
+        let mut bucket = self.lock();
+        bucket.refill();
+
+        // unlimited, nothing to wait for
+        let rate = bucket.rate?;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+
+        if rate <= 0.0 {
+            return Some(MAX_WAIT);
+        }
+
+        let wait = Duration::from_secs_f32((1.0 - bucket.tokens) / rate);
+        Some(wait.min(MAX_WAIT))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        self.bucket.lock().expect("rate limiter lock poisoned")
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter").field("rate", &self.rate()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_wait() {
+        let limiter = RateLimiter::new(100.0, 5);
+        for _ in 0..5 {
+            assert!(limiter.try_acquire());
+        }
+        assert!(!limiter.try_acquire());
+
+        let start = Instant::now();
+        task::block_on(async {
+            for _ in 0..5 {
+                limiter.acquire().await;
+            }
+        });
+
+        // 5 tokens at 100/s is 50ms, give or take scheduling
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn unlimited_and_shared() {
+        let limiter = RateLimiter::unlimited();
+        assert!((0..1000).all(|_| limiter.try_acquire()));
+
+        // a paused limiter hands out what's left in the bucket and then nothing
+        let handle = limiter.clone();
+        handle.set_burst(2);
+        handle.set_rate(Some(0.0));
+        assert_eq!(limiter.rate(), Some(0.0));
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// # PoolMetrics
+///
+/// A point-in-time snapshot of the counters and gauges a `WorkerPool` maintains while it works.
+///
+/// Counters only ever go up for the lifetime of the pool, so the usual way to get a rate for an
+/// interval is to keep the previous snapshot around and compare against it. (See
+/// `results_per_sec_since`.)
+#[derive(Debug, Copy, Clone)]
+pub struct PoolMetrics {
+    /// Time since the pool started working
+    pub elapsed: Duration,
+    /// Jobs handed to a worker
+    pub jobs_started: usize,
+    /// Jobs that finished on their own (`JobStatus::Done`)
+    pub jobs_completed: usize,
+    /// Jobs that finished because they were told to stop (`JobStatus::Stopped`)
+    pub jobs_stopped: usize,
+    /// Jobs that reported `JobStatus::Failed`
+    pub jobs_failed: usize,
+    /// Jobs cancelled for running past their timeout
+    pub jobs_timed_out: usize,
+    /// Tasks discarded unstarted because they waited in the queue too long
+    pub tasks_expired: usize,
+    /// Results forwarded from workers to the output channel
+    pub results_emitted: usize,
+    /// Tasks waiting in the queue for a worker
+    pub queue_depth: usize,
+    /// Workers currently working
+    pub cur_workers: usize,
+    /// Workers the pool is trying to have
+    pub target_workers: usize,
+    /// Start-to-finish time of every worker that has finished
+    pub worker_lifetimes: DurationStats,
+    /// How far behind schedule each open-loop job started
+    pub start_lag: DurationStats,
+    /// Total time spent blocked on sending to the output channel
+    pub output_wait: Duration,
+}
+
+impl PoolMetrics {
+    fn new() -> Self {
+        Self {
+            elapsed: Duration::from_secs(0),
+            jobs_started: 0,
+            jobs_completed: 0,
+            jobs_stopped: 0,
+            jobs_failed: 0,
+            jobs_timed_out: 0,
+            tasks_expired: 0,
+            results_emitted: 0,
+            queue_depth: 0,
+            cur_workers: 0,
+            target_workers: 0,
+            worker_lifetimes: DurationStats::default(),
+            start_lag: DurationStats::default(),
+            output_wait: Duration::from_secs(0),
+        }
+    }
+
+    /// Jobs that have been started and haven't finished yet
+    pub fn jobs_in_flight(&self) -> usize {
+        self.jobs_started
+            - self.jobs_completed
+            - self.jobs_stopped
+            - self.jobs_failed
+            - self.jobs_timed_out
+    }
+
+    /// Average results per second over the whole life of the pool
+    pub fn results_per_sec(&self) -> f32 {
+        rate(self.results_emitted, self.elapsed)
+    }
+
+    /// Results per second in the interval between an `earlier` snapshot and this one
+    pub fn results_per_sec_since(&self, earlier: &PoolMetrics) -> f32 {
+        rate(
+            self.results_emitted.saturating_sub(earlier.results_emitted),
+            self.elapsed.checked_sub(earlier.elapsed).unwrap_or_default(),
+        )
+    }
+}
+
+fn rate(count: usize, elapsed: Duration) -> f32 {
+    match elapsed.as_secs_f32() {
+        secs if secs > 0.0 => count as f32 / secs,
+        _ => 0.0,
+    }
+}
+
+/// Running count, total, min and max of a series of durations
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DurationStats {
+    pub count: usize,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl DurationStats {
+    pub fn record(&mut self, duration: Duration) {
+        if self.count == 0 || duration < self.min {
+            self.min = duration;
+        }
+        if duration > self.max {
+            self.max = duration;
+        }
+
+        self.count += 1;
+        self.total += duration;
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::from_secs(0),
+            n => self.total / n as u32,
+        }
+    }
+}
+
+/// Cloneable handle to a pool's metrics.
+///
+/// `WorkerPool::work` holds onto the pool for as long as it runs, so this is how other tasks
+/// (an autoscaler, a progress reporter) read the numbers while it's going.
+#[derive(Clone)]
+pub struct MetricsHandle {
+    inner: Arc<Mutex<Recorder>>,
+}
+
+struct Recorder {
+    started: Instant,
+    metrics: PoolMetrics,
+}
+
+impl MetricsHandle {
+    pub(crate) fn new() -> Self {
+        let recorder = Recorder { started: Instant::now(), metrics: PoolMetrics::new() };
+        Self { inner: Arc::new(Mutex::new(recorder)) }
+    }
+
+    /// Copies out the current state of the metrics
+    pub fn snapshot(&self) -> PoolMetrics {
+        let recorder = self.inner.lock().expect("metrics lock poisoned");
+        let mut metrics = recorder.metrics;
+        metrics.elapsed = recorder.started.elapsed();
+        metrics
+    }
+
+    /// Resets the clock that `elapsed` and the rates are measured from
+    pub(crate) fn restart_clock(&self) {
+        self.inner.lock().expect("metrics lock poisoned").started = Instant::now();
+    }
+
+    pub(crate) fn update<T>(&self, f: impl FnOnce(&mut PoolMetrics) -> T) -> T {
+        f(&mut self.inner.lock().expect("metrics lock poisoned").metrics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_stats() {
+        let mut stats = DurationStats::default();
+        stats.record(Duration::from_millis(30));
+        stats.record(Duration::from_millis(10));
+        stats.record(Duration::from_millis(20));
+
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.min, Duration::from_millis(10));
+        assert_eq!(stats.max, Duration::from_millis(30));
+        assert_eq!(stats.mean(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn interval_rate() {
+        let mut earlier = PoolMetrics::new();
+        earlier.elapsed = Duration::from_secs(1);
+        earlier.results_emitted = 100;
+
+        let mut later = earlier;
+        later.elapsed = Duration::from_secs(3);
+        later.results_emitted = 500;
+
+        assert_eq!(later.results_per_sec_since(&earlier), 200.0);
+        assert!((later.results_per_sec() - 166.666).abs() < 0.01);
+    }
+}
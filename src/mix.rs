@@ -0,0 +1,445 @@
+use log::warn;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{
+    histogram::Histogram,
+    http::{HttpRequest, HttpResponse},
+    limiter::RateLimiter,
+    queue::{Queued, TaskQueue},
+    rng::Rng,
+    sample::Sample,
+    template::RequestTemplate,
+};
+
+enum Source {
+    Fixed(HttpRequest),
+    Template(RequestTemplate),
+}
+
+struct Endpoint {
+    name: String,
+    source: Source,
+    weight: u32,
+    cap: Option<RateLimiter>,
+}
+
+/// # TrafficMix
+///
+/// A queue for a `WorkerPool` running `http_worker` that never runs dry: every time the pool
+/// wants a task, it gets a single-shot request to one of several endpoints, picked by weight.
+///
+/// Picks come from a seeded generator, so the same seed gives the same sequence of endpoints.
+/// An endpoint can also be capped at a rate of its own. While it's at its cap, its share of
+/// the traffic goes to the others; if every endpoint is at its cap, the request waits on its
+/// endpoint's cap before it's made.
+///
+/// Each endpoint's responses are tagged with its index, in the order endpoints were added, and
+/// `MixSummary` uses that to break the results down per endpoint.
+///
+/// Endpoints can be fixed requests, or `RequestTemplate`s rendered afresh for every pick.
+/// Every request is its own job, so run the mix on `pooled_http_worker` to have each worker
+/// keep its connections open from one request to the next.
+///
+/// ```no_run
+/// # use async_std::{sync::channel, task};
+/// use clobber::{pooled_http_worker, HttpConnections, HttpRequest, TrafficMix, WorkerPool};
+///
+/// let mix = TrafficMix::new(7)
+///     .with_endpoint("home", HttpRequest::get("http://localhost:8000/").unwrap(), 8)
+///     .with_endpoint("search", HttpRequest::get("http://localhost:8000/search?q=a").unwrap(), 3)
+///     .with_capped_endpoint(
+///         "checkout",
+///         HttpRequest::new("POST", "http://localhost:8000/checkout").unwrap(),
+///         1,
+///         20.0,
+///     );
+/// let mut summary = mix.summary();
+///
+/// let (send, recv) = channel(1024);
+/// let mut pool =
+///     WorkerPool::with_context(pooled_http_worker, send, 32, HttpConnections::default, drop)
+///         .with_queue(mix);
+///
+/// task::spawn(async move {
+///     while let Ok(response) = recv.recv().await {
+///         summary.add(&response);
+///     }
+/// });
+/// task::block_on(pool.work());
+/// ```
+pub struct TrafficMix {
+    endpoints: Vec<Endpoint>,
+    rng: Rng,
+    remaining: Option<usize>,
+    pushed: VecDeque<Queued<HttpRequest>>,
+}
+
+impl TrafficMix {
+    pub fn new(seed: u64) -> Self {
+        Self { endpoints: vec![], rng: Rng::new(seed), remaining: None, pushed: VecDeque::new() }
+    }
+
+    /// Adds an endpoint that gets `weight` out of every total-weight requests
+    pub fn with_endpoint(mut self, name: &str, request: HttpRequest, weight: u32) -> Self {
+        self.add(name, Source::Fixed(request), weight, None);
+        self
+    }
+
+    /// Adds an endpoint that never gets more than `rate` requests per second
+    pub fn with_capped_endpoint(
+        mut self,
+        name: &str,
+        request: HttpRequest,
+        weight: u32,
+        rate: f32,
+    ) -> Self {
+        self.add(name, Source::Fixed(request), weight, Some(RateLimiter::new(rate, 1)));
+        self
+    }
+
+    /// Adds an endpoint that renders a new request from `template` every time it's picked
+    pub fn with_templated_endpoint(
+        mut self,
+        name: &str,
+        template: RequestTemplate,
+        weight: u32,
+    ) -> Self {
+        self.add(name, Source::Template(template), weight, None);
+        self
+    }
+
+    /// Hands out this many requests and then runs dry, instead of going until the pool stops
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.remaining = Some(count);
+        self
+    }
+
+    /// Endpoint names, in the order they were added
+    pub fn names(&self) -> Vec<&str> {
+        self.endpoints.iter().map(|endpoint| endpoint.name.as_str()).collect()
+    }
+
+    /// An empty summary with a row for each endpoint
+    pub fn summary(&self) -> MixSummary {
+        MixSummary::new(&self.names())
+    }
+
+    /// Picks the next request, or `None` once the mix has run dry. A template that fails to
+    /// render is logged and skipped, which also gives `None`, but still counts as a request.
+    pub fn next_request(&mut self) -> Option<HttpRequest> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+
+        let (i, wait) = self.pick()?;
+        self.remaining = self.remaining.map(|n| n - 1);
+
+        let endpoint = &mut self.endpoints[i];
+        let request = match &mut endpoint.source {
+            Source::Fixed(request) => request.clone(),
+            Source::Template(template) => match template.render() {
+                Ok(request) => request.with_endpoint(i).with_count(1),
+                Err(e) => {
+                    warn!("couldn't render a request for {}: {}", endpoint.name, e);
+                    return None;
+                }
+            },
+        };
+
+        match (wait, endpoint.cap.clone()) {
+            (true, Some(cap)) => Some(request.with_rate_limit(cap)),
+            _ => Some(request),
+        }
+    }
+
+    fn add(&mut self, name: &str, source: Source, weight: u32, cap: Option<RateLimiter>) {
+        let i = self.endpoints.len();
+        let source = match source {
+            Source::Fixed(request) => Source::Fixed(request.with_endpoint(i).with_count(1)),
+            source => source,
+        };
+        self.endpoints.push(Endpoint { name: name.to_string(), source, weight, cap });
+    }
+
+    /// Which endpoint goes next, and whether it has to wait for its cap
+    fn pick(&mut self) -> Option<(usize, bool)> {
+        let weighted: Vec<usize> =
+            (0..self.endpoints.len()).filter(|&i| self.endpoints[i].weight > 0).collect();
+
+        let mut open = weighted.clone();
+        while !open.is_empty() {
+            let i = self.choose(&open);
+            match self.endpoints[i].cap.as_ref() {
+                Some(cap) if !cap.try_acquire() => open.retain(|&j| j != i),
+                _ => return Some((i, false)),
+            }
+        }
+
+        if weighted.is_empty() {
+            return None;
+        }
+
+        // everything is at its cap, so this one waits its turn
+        Some((self.choose(&weighted), true))
+    }
+
+    /// A weighted random pick from `among`, which isn't empty
+    fn choose(&mut self, among: &[usize]) -> usize {
+        let total: u64 = among.iter().map(|&i| self.endpoints[i].weight as u64).sum();
+        let mut roll = self.rng.next_u64() % total;
+
+        for &i in among {
+            let weight = self.endpoints[i].weight as u64;
+            if roll < weight {
+                return i;
+            }
+            roll -= weight;
+        }
+
+        among[among.len() - 1]
+    }
+
+    fn exhausted(&self) -> bool {
+        self.remaining == Some(0) || self.endpoints.iter().all(|endpoint| endpoint.weight == 0)
+    }
+}
+
+/// Tasks pushed onto the pool go out first, ahead of the mix. `len` only counts those, since
+/// the mix's own requests are made up as they're needed.
+impl TaskQueue<HttpRequest> for TrafficMix {
+    fn push(&mut self, task: Queued<HttpRequest>) {
+        self.pushed.push_back(task);
+    }
+
+    fn pop(&mut self) -> Option<Queued<HttpRequest>> {
+        self.pushed.pop_front().or_else(|| self.next_request().map(Queued::new))
+    }
+
+    fn len(&self) -> usize {
+        self.pushed.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pushed.is_empty() && self.exhausted()
+    }
+}
+
+/// Results for one endpoint of a `TrafficMix`
+#[derive(Debug, Clone)]
+pub struct EndpointSummary {
+    pub name: String,
+    pub requests: u64,
+    /// Requests that failed, going by `Sample::failed`: they errored or failed a check
+    pub errors: u64,
+    pub statuses: BTreeMap<u16, u64>,
+    pub latency: Histogram,
+}
+
+impl EndpointSummary {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            requests: 0,
+            errors: 0,
+            statuses: BTreeMap::new(),
+            latency: Histogram::new(),
+        }
+    }
+
+    /// Fraction of requests that were errors, from 0 to 1
+    pub fn error_rate(&self) -> f32 {
+        match self.requests {
+            0 => 0.0,
+            requests => self.errors as f32 / requests as f32,
+        }
+    }
+}
+
+/// # MixSummary
+///
+/// Adds up `HttpResponse`s per endpoint, going by the endpoint tag on each response.
+/// Responses tagged with an endpoint it doesn't have a row for are ignored.
+#[derive(Debug, Clone)]
+pub struct MixSummary {
+    pub endpoints: Vec<EndpointSummary>,
+}
+
+impl MixSummary {
+    pub fn new(names: &[&str]) -> Self {
+        Self { endpoints: names.iter().map(|name| EndpointSummary::new(name)).collect() }
+    }
+
+    pub fn add(&mut self, response: &HttpResponse) {
+        let endpoint = match self.endpoints.get_mut(response.endpoint) {
+            Some(endpoint) => endpoint,
+            None => return,
+        };
+
+        endpoint.requests += 1;
+        endpoint.latency.record(response.latency);
+        if let Some(status) = response.status {
+            *endpoint.statuses.entry(status).or_default() += 1;
+        }
+        if response.failed() {
+            endpoint.errors += 1;
+        }
+    }
+
+    /// Requests across every endpoint
+    pub fn requests(&self) -> u64 {
+        self.endpoints.iter().map(|endpoint| endpoint.requests).sum()
+    }
+
+    /// Fraction of all requests that went to `endpoint`, from 0 to 1
+    pub fn share(&self, endpoint: usize) -> f32 {
+        match (self.requests(), self.endpoints.get(endpoint)) {
+            (0, _) | (_, None) => 0.0,
+            (total, Some(endpoint)) => endpoint.requests as f32 / total as f32,
+        }
+    }
+}
+
+impl Display for MixSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, endpoint) in self.endpoints.iter().enumerate() {
+            writeln!(
+                f,
+                "{:<16} {:>8} ({:>5.1}%)  errors {:>5.1}%  p50 {:?}, p99 {:?}",
+                endpoint.name,
+                endpoint.requests,
+                self.share(i) * 100.0,
+                endpoint.error_rate() * 100.0,
+                endpoint.latency.percentile(50.0),
+                endpoint.latency.percentile(99.0),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http_worker, pooled_http_worker, testing::http_server, Check, Checks, HttpConnections,
+        WorkerPool,
+    };
+    use async_std::sync::channel;
+    use futures_await_test::async_test;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn endpoint(path: &str) -> HttpRequest {
+        HttpRequest::get(&format!("http://127.0.0.1:1{}", path)).unwrap()
+    }
+
+    fn picks(mut mix: TrafficMix, n: usize) -> Vec<usize> {
+        (0..n).map(|_| mix.next_request().unwrap().endpoint).collect()
+    }
+
+    #[test]
+    fn weights_and_caps() {
+        let mix = || {
+            TrafficMix::new(3)
+                .with_endpoint("a", endpoint("/a"), 3)
+                .with_endpoint("b", endpoint("/b"), 1)
+                .with_endpoint("never", endpoint("/c"), 0)
+        };
+
+        let first = picks(mix(), 4000);
+        assert_eq!(first, picks(mix(), 4000));
+
+        let a = first.iter().filter(|&&i| i == 0).count() as f32 / 4000.0;
+        assert!((a - 0.75).abs() < 0.03, "{}", a);
+        assert!(!first.contains(&2));
+
+        // a capped endpoint's share goes elsewhere once it's used up its burst
+        let mut capped = TrafficMix::new(3)
+            .with_endpoint("a", endpoint("/a"), 1)
+            .with_capped_endpoint("b", endpoint("/b"), 100, 0.0)
+            .with_count(50);
+        let picks: Vec<usize> =
+            std::iter::from_fn(|| capped.next_request()).map(|r| r.endpoint).collect();
+        assert_eq!(picks.len(), 50);
+        assert_eq!(picks.iter().filter(|&&i| i == 1).count(), 1);
+        assert!(capped.is_empty());
+    }
+
+    /// Replies 204 on `/ok`, and 500 on anything else, counting connections
+    async fn server(connections: Arc<AtomicUsize>) -> String {
+        let respond = |head: &[u8]| -> &'static [u8] {
+            match head.starts_with(b"GET /ok ") {
+                true => b"HTTP/1.1 204 No Content\r\n\r\n",
+                false => b"HTTP/1.1 500 Oops\r\nContent-Length: 0\r\n\r\n",
+            }
+        };
+        http_server(respond, connections).await
+    }
+
+    #[async_test]
+    async fn per_endpoint_summary() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let addr = server(connections.clone()).await;
+        let url = |path: &str| HttpRequest::get(&format!("http://{}{}", addr, path)).unwrap();
+        let status = |low, high| Checks::new().with("status", Check::Status(low, high));
+        let mix = TrafficMix::new(11)
+            .with_endpoint("ok", url("/ok"), 1)
+            .with_endpoint("broken", url("/broken").with_checks(status(200, 299)), 1)
+            .with_endpoint("expected", url("/expected").with_checks(status(500, 599)), 1)
+            .with_count(60);
+        let mut summary = mix.summary();
+
+        let (send, recv) = channel(64);
+        let mut pool =
+            WorkerPool::with_context(pooled_http_worker, send, 4, HttpConnections::default, drop)
+                .with_queue(mix);
+        pool.work().await;
+
+        while let Ok(response) = recv.try_recv() {
+            summary.add(&response);
+        }
+
+        let (ok, broken, expected) =
+            (&summary.endpoints[0], &summary.endpoints[1], &summary.endpoints[2]);
+        assert_eq!(summary.requests(), 60);
+        assert!(ok.requests > 0 && broken.requests > 0 && expected.requests > 0);
+        assert_eq!(ok.statuses.get(&204), Some(&ok.requests));
+        assert_eq!(ok.error_rate(), 0.0);
+        assert_eq!(broken.error_rate(), 1.0);
+        // a 500 its checks accept isn't an error
+        assert_eq!(expected.statuses.get(&500), Some(&expected.requests));
+        assert_eq!(expected.error_rate(), 0.0);
+        assert!(summary.to_string().starts_with("ok"));
+
+        // each worker slot keeps its connection from one request to the next
+        assert!(connections.load(Ordering::SeqCst) <= 4, "{:?}", connections);
+    }
+
+    #[async_test]
+    async fn replaces_an_endless_mix() {
+        let addr = server(Default::default()).await;
+        let url = |path: &str| HttpRequest::get(&format!("http://{}{}", addr, path)).unwrap();
+
+        let (send, recv) = channel(64);
+        let mut pool = WorkerPool::new(http_worker, send, 1)
+            .with_queue(TrafficMix::new(1).with_endpoint("endless", url("/broken"), 1));
+        pool.push(url("/ok").with_count(1));
+
+        // the pushed request comes along, but the old mix's own requests don't
+        let mix = TrafficMix::new(2).with_endpoint("ok", url("/ok"), 1).with_count(3);
+        let mut pool = pool.with_queue(mix);
+        pool.work().await;
+
+        let mut statuses = vec![];
+        while let Ok(response) = recv.try_recv() {
+            statuses.push(response.status);
+        }
+        assert_eq!(statuses, vec![Some(204); 4]);
+    }
+}
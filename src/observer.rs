@@ -0,0 +1,68 @@
+use log::debug;
+use std::time::Duration;
+
+use crate::pool::WorkerPoolCommand;
+
+/// Something that happened inside a `WorkerPool`.
+///
+/// Workers are identified by a number the pool hands out in the order they were started; ids
+/// are never reused within a pool.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PoolEvent {
+    /// A task was popped from the queue and handed to a new worker
+    WorkerStarted { worker: usize },
+    /// A worker's job returned `JobStatus::Done`
+    WorkerDone { worker: usize, lifetime: Duration },
+    /// A worker's job returned `JobStatus::Stopped`
+    WorkerStopped { worker: usize, lifetime: Duration },
+    /// A worker's job returned `JobStatus::Failed`
+    WorkerFailed { worker: usize, lifetime: Duration },
+    /// A worker's job ran past its timeout and was cancelled
+    WorkerTimedOut { worker: usize, lifetime: Duration },
+    /// A task was discarded unstarted after waiting in the queue past its deadline
+    TaskExpired { waited: Duration },
+    /// The pool sent a stop message because it has more workers than it wants
+    StopRequested,
+    /// The target number of workers changed
+    TargetChanged { from: usize, to: usize },
+    /// A command arrived on the command channel
+    CommandReceived(WorkerPoolCommand),
+    /// The output channel was full, so the pool had to wait to hand off a result
+    OutputBackpressure { waited: Duration },
+}
+
+/// # PoolObserver
+///
+/// Hook for plugging tracing, alerting, or anything else into a pool's internals. Observers
+/// are called synchronously from the pool's loop, so they should be quick about it.
+///
+/// Any `FnMut(&PoolEvent)` closure is an observer:
+/// ```
+/// # use async_std::sync::channel;
+/// # use clobber::{Job, JobStatus, PoolEvent, WorkerPool};
+/// # async fn work(_: Job<(), ()>) -> JobStatus { JobStatus::Done }
+/// let (send, _recv) = channel(1);
+/// let mut pool = WorkerPool::new(work, send, 1);
+/// pool.add_observer(|event: &PoolEvent| eprintln!("{:?}", event));
+/// ```
+pub trait PoolObserver: Send {
+    fn on_event(&mut self, event: &PoolEvent);
+}
+
+impl<F> PoolObserver for F
+where
+    F: FnMut(&PoolEvent) + Send,
+{
+    fn on_event(&mut self, event: &PoolEvent) {
+        self(event)
+    }
+}
+
+/// Writes every event to the `log` crate at `debug` level.
+pub struct LogObserver;
+
+impl PoolObserver for LogObserver {
+    fn on_event(&mut self, event: &PoolEvent) {
+        debug!("{:?}", event);
+    }
+}
@@ -0,0 +1,162 @@
+use async_std::task;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::rng::Rng;
+
+/// How long a worker waits between iterations
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pacing {
+    /// Back to back, as fast as the target allows
+    None,
+    /// The same think time after every iteration
+    Fixed(Duration),
+    /// Think time picked evenly between `min` and `max`
+    Uniform { min: Duration, max: Duration },
+    /// Think time from an exponential distribution, like independent users arriving
+    Exponential { mean: Duration },
+    /// Starts iterations this far apart, however long each takes. One that overruns is
+    /// followed immediately by the next.
+    Cycle(Duration),
+}
+
+/// Seeds for pacers that aren't given one, so they don't all think in lockstep
+static NEXT_SEED: AtomicU64 = AtomicU64::new(0);
+
+/// # Pacer
+///
+/// Spaces out the iterations of a worker's loop according to a `Pacing`, so a worker behaves
+/// like a user working through a session rather than a client hammering the target.
+///
+/// Call `next_delay` (or `wait`) once per iteration, just before starting it. The first call
+/// never waits. Think times are measured from the end of one iteration to the start of the
+/// next, cycle times from start to start.
+///
+/// Random think times come from a seed. Pacers made without one each get a different seed, in
+/// the order they're made.
+///
+/// ```
+/// use clobber::{Job, JobStatus, Pacer, Pacing};
+/// use std::time::Duration;
+///
+/// async fn session(job: Job<(), Duration>) -> JobStatus {
+///     let mut pacer = Pacer::new(Pacing::Exponential { mean: Duration::from_secs(3) });
+///
+///     loop {
+///         // wakes early if the pool wants this job to stop
+///         if job.sleep(pacer.next_delay()).await {
+///             return JobStatus::Stopped;
+///         }
+///
+///         // ... browse a page
+///         # return JobStatus::Done;
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    pacing: Pacing,
+    rng: Rng,
+    /// When the next iteration is due to start, for cycle times
+    next_start: Option<Instant>,
+    started: bool,
+}
+
+impl Pacer {
+    pub fn new(pacing: Pacing) -> Self {
+        let seed = NEXT_SEED.fetch_add(1, Ordering::Relaxed);
+        Self { pacing, rng: Rng::new(seed), next_start: None, started: false }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    /// How long to wait before starting the next iteration
+    pub fn next_delay(&mut self) -> Duration {
+        if !self.started {
+            self.started = true;
+            self.next_start = Some(Instant::now());
+            return Duration::from_secs(0);
+        }
+
+        match self.pacing {
+            Pacing::None => Duration::from_secs(0),
+            Pacing::Fixed(think) => think,
+            Pacing::Uniform { min, max } => {
+                let spread = max.checked_sub(min).unwrap_or_default();
+                min + spread.mul_f64(self.rng.next_f64())
+            }
+            Pacing::Exponential { mean } => {
+                Duration::from_secs_f64(self.rng.exponential(mean.as_secs_f64()))
+            }
+            Pacing::Cycle(cycle) => {
+                let now = Instant::now();
+                let due = self.next_start.map_or(now, |last| last + cycle).max(now);
+                self.next_start = Some(due);
+                due - now
+            }
+        }
+    }
+
+    /// Sleeps until the next iteration should start
+    pub async fn wait(&mut self) {
+        let delay = self.next_delay();
+        if delay > Duration::from_secs(0) {
+            task::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_await_test::async_test;
+
+    fn delays(pacing: Pacing, n: usize) -> Vec<Duration> {
+        let mut pacer = Pacer::new(pacing).with_seed(5);
+        (0..n).map(|_| pacer.next_delay()).collect()
+    }
+
+    #[test]
+    fn think_times() {
+        let ms = Duration::from_millis;
+        assert_eq!(delays(Pacing::Fixed(ms(30)), 3), vec![ms(0), ms(30), ms(30)]);
+
+        let uniform = delays(Pacing::Uniform { min: ms(10), max: ms(20) }, 1000);
+        assert_eq!(uniform, delays(Pacing::Uniform { min: ms(10), max: ms(20) }, 1000));
+        assert!(uniform[1..].iter().all(|&d| d >= ms(10) && d <= ms(20)));
+
+        let exponential = delays(Pacing::Exponential { mean: ms(100) }, 10_001);
+        let mean = exponential.iter().sum::<Duration>() / 10_000;
+        assert!(mean > ms(95) && mean < ms(105), "{:?}", mean);
+    }
+
+    #[async_test]
+    async fn cycle_time() {
+        let cycle = Duration::from_millis(50);
+        let mut pacer = Pacer::new(Pacing::Cycle(cycle));
+        let start = Instant::now();
+
+        for i in 0..4 {
+            pacer.wait().await;
+            // the third iteration overruns, so the fourth starts right after it
+            if i == 2 {
+                task::sleep(Duration::from_millis(80)).await;
+            } else {
+                task::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100 + 80 + 10), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(250), "{:?}", elapsed);
+    }
+}
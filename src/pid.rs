@@ -64,4 +64,9 @@ impl PidController {
     pub fn output(&self) -> f32 {
         self.p.output() + self.i.output() + self.d.output()
     }
+
+    /// What each term contributes to the output: proportional, integral, derivative
+    pub fn terms(&self) -> (f32, f32, f32) {
+        (self.p.output(), self.i.output(), self.d.output())
+    }
 }
@@ -1,12 +1,24 @@
 #![allow(dead_code)]
 
 use async_std::{
+    future,
     prelude::*,
     sync::{channel, Receiver, Sender},
     task,
 };
 use crossbeam_channel::{self, Receiver as CrossbeamReceiver, Sender as CrossbeamSender};
-use std::collections::VecDeque;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    arrivals::{ArrivalClock, Arrivals},
+    limiter::RateLimiter,
+    metrics::{MetricsHandle, PoolMetrics},
+    observer::{PoolEvent, PoolObserver},
+    queue::{Fifo, Queued, TaskQueue},
+};
 
 /// # WorkerPool
 ///
@@ -26,19 +38,30 @@ use std::collections::VecDeque;
 /// I'm not incredibly concerned about allocations in this model; `WorkerPool` is a higher level
 /// abstraction than something like `crossbeam`. I built this for a client-side CLI use case to
 /// put a load test target under variable load from long-running workers that just sit and loop
-/// TCP connections against a server.
+/// TCP connections against a server. `tcp_worker` is that worker.
+///
+/// ## Worker context
 ///
-pub struct WorkerPool<In, Out, F> {
+/// Each worker slot can carry state of type `Ctx` that outlives any single job: a keep-alive
+/// connection, a scratch buffer, a seeded RNG. The pool creates a context with `init` when it
+/// needs a new slot, hands a clone of it to every job that runs in that slot, and passes it to
+/// `teardown` when the slot goes away or `work` returns. A slot whose job is still running when
+/// the pool is stopped is torn down once the job reports back, on the next call to `work` or when
+/// the pool is dropped; one still running after that is dropped without a teardown. Contexts are
+/// cloned per job, so `Ctx` is usually a cheap handle like an `Arc<Mutex<_>>`. See
+/// `WorkerPool::with_context`.
+///
+pub struct WorkerPool<In, Out, F, Ctx = ()> {
     /// How many workers we want
     num_workers: usize,
     /// How many workers we actually have
     cur_workers: usize,
     /// Outstanding tasks
-    queue: VecDeque<In>,
+    queue: Box<dyn TaskQueue<In>>,
     /// Output channel
     output: Sender<Out>,
     /// The async function that a worker performs
-    task: fn(Job<In, Out>) -> F,
+    task: fn(Job<In, Out, Ctx>) -> F,
     /// Used to get completed work from workers
     results_channel: (Sender<Out>, Receiver<Out>),
     /// Used to stop workers before they self-terminate
@@ -48,31 +71,78 @@ pub struct WorkerPool<In, Out, F> {
     command_events: (CrossbeamSender<WorkerPoolCommand>, CrossbeamReceiver<WorkerPoolCommand>),
 
     outstanding_stops: usize,
+    /// Winding down after a `Drain` command
+    draining: bool,
+    /// Counters and gauges, shared with anyone holding a `MetricsHandle`
+    metrics: MetricsHandle,
+    /// Notified of everything interesting that happens in the pool
+    observers: Vec<Box<dyn PoolObserver>>,
+    /// Id to give the next worker we start
+    next_worker_id: usize,
+    /// Cancel jobs that run longer than this, unless the task has its own timeout
+    job_timeout: Option<Duration>,
+    /// Discard tasks that have been queued for longer than this
+    queue_timeout: Option<Duration>,
+    /// Creates the context for a new worker slot
+    context_init: fn() -> Ctx,
+    /// Cleans up the context of a slot that's going away
+    context_teardown: fn(Ctx),
+    /// Contexts of slots whose last job finished, waiting for the next one
+    idle_contexts: Vec<Ctx>,
+    /// Contexts of running workers, by worker id
+    active_contexts: HashMap<usize, Ctx>,
+    /// Shared by every job the pool starts
+    limiter: RateLimiter,
+    /// Start tasks on this schedule instead of whenever a worker is free
+    open_loop: Option<Arrivals>,
+    /// Tracks the open-loop schedule while the pool is working
+    arrival_clock: Option<ArrivalClock>,
 }
 
+/// Sent by a worker when its job returns, along with how long the worker was alive.
 #[derive(Debug, Copy, Clone)]
 enum WorkerEvent {
-    WorkerDone,
-    WorkerStopped,
+    Done { worker: usize, lifetime: Duration },
+    Stopped { worker: usize, lifetime: Duration },
+    Failed { worker: usize, lifetime: Duration },
+    TimedOut { worker: usize, lifetime: Duration },
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum WorkerPoolCommand {
     Stop,
+    /// Stops starting new work, asks every running job to stop, and ends `work` once they
+    /// have all finished and their results have been sent on
+    Drain,
     SetWorkerCount(usize),
+    /// Changes the rate of the pool's shared `RateLimiter`. `None` removes the limit.
+    SetRateLimit(Option<f32>),
 }
 
 // todo command channel
 
-pub struct Job<In, Out> {
+pub struct Job<In, Out, Ctx = ()> {
     pub task: In,
     pub close: Receiver<()>,
     pub results: Sender<Out>,
+    /// State belonging to the worker slot this job is running in
+    pub context: Ctx,
+    /// Rate limiter shared by all jobs in the pool. Jobs that want to respect the pool's rate
+    /// limit call `limiter.acquire().await` before each unit of work.
+    pub limiter: RateLimiter,
+    /// When the pool meant to start this job, if it's running open-loop
+    pub scheduled: Option<Instant>,
 }
 
 impl<In, Out> Job<In, Out> {
     pub fn new(task: In, close: Receiver<()>, results: Sender<Out>) -> Self {
-        Self { task, close, results }
+        Self::with_context(task, close, results, ())
+    }
+}
+
+impl<In, Out, Ctx> Job<In, Out, Ctx> {
+    pub fn with_context(task: In, close: Receiver<()>, results: Sender<Out>, context: Ctx) -> Self {
+        Self { task, close, results, context, limiter: RateLimiter::unlimited(), scheduled: None }
     }
 
     pub fn stop_requested(&self) -> bool {
@@ -81,11 +151,22 @@ impl<In, Out> Job<In, Out> {
             Err(_) => false,
         }
     }
+
+    /// Sleeps for `duration`, waking early if the pool asks this job to stop. Returns whether
+    /// it did, in which case the job should stop: the request has been used up.
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        if duration == Duration::from_secs(0) {
+            return self.stop_requested();
+        }
+
+        matches!(future::timeout(duration, self.close.recv()).await, Ok(Ok(())))
+    }
 }
 
 pub enum JobStatus {
     Done,
     Stopped,
+    Failed,
     Running,
 }
 
@@ -96,6 +177,46 @@ where
     F: Future<Output = JobStatus> + Send + 'static,
 {
     pub fn new(task: fn(Job<In, Out>) -> F, output: Sender<Out>, num_workers: usize) -> Self {
+        Self::with_context(task, output, num_workers, || (), |_| ())
+    }
+}
+
+impl<In, Out, F, Ctx> WorkerPool<In, Out, F, Ctx>
+where
+    In: Send + Sync + 'static,
+    Out: Send + Sync + 'static,
+    F: Future<Output = JobStatus> + Send + 'static,
+    Ctx: Clone + Send + Sync + 'static,
+{
+    /// Creates a pool whose worker slots each carry a context.
+    ///
+    /// ```
+    /// # use async_std::sync::channel;
+    /// # use clobber::{Job, JobStatus, WorkerPool};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// // every job run in a slot appends to the same buffer
+    /// async fn work(job: Job<u8, (), Arc<Mutex<Vec<u8>>>>) -> JobStatus {
+    ///     job.context.lock().unwrap().push(job.task);
+    ///     JobStatus::Done
+    /// }
+    ///
+    /// let (send, _recv) = channel(1);
+    /// let pool = WorkerPool::with_context(
+    ///     work,
+    ///     send,
+    ///     4,
+    ///     || Arc::new(Mutex::new(Vec::with_capacity(1024))),
+    ///     |buffer| drop(buffer),
+    /// );
+    /// ```
+    pub fn with_context(
+        task: fn(Job<In, Out, Ctx>) -> F,
+        output: Sender<Out>,
+        num_workers: usize,
+        init: fn() -> Ctx,
+        teardown: fn(Ctx),
+    ) -> Self {
         Self {
             task,
             output,
@@ -105,11 +226,49 @@ where
             close_channel: channel(num_workers),
             worker_events: crossbeam_channel::unbounded(),
             command_events: crossbeam_channel::unbounded(),
-            queue: VecDeque::with_capacity(num_workers),
+            queue: Box::new(Fifo::with_capacity(num_workers)),
             outstanding_stops: 0,
+            draining: false,
+            metrics: MetricsHandle::new(),
+            observers: vec![],
+            next_worker_id: 0,
+            job_timeout: None,
+            queue_timeout: None,
+            context_init: init,
+            context_teardown: teardown,
+            idle_contexts: vec![],
+            active_contexts: HashMap::new(),
+            limiter: RateLimiter::unlimited(),
+            open_loop: None,
+            arrival_clock: None,
         }
     }
 
+    /// Replaces the discipline used to pick which queued task runs next.
+    /// Any tasks already pushed are moved over to the new queue. Tasks the old queue would have
+    /// made up itself, like a `TrafficMix`'s requests, go with it.
+    ///
+    /// ```
+    /// # use async_std::sync::channel;
+    /// # use clobber::{Job, JobStatus, PriorityQueue, WorkerPool};
+    /// # async fn work(_: Job<(u8, &'static str), ()>) -> JobStatus { JobStatus::Done }
+    /// let (send, _recv) = channel(1);
+    /// let pool = WorkerPool::new(work, send, 4)
+    ///     .with_queue(PriorityQueue::new(|task: &(u8, &str)| task.0 as usize));
+    /// ```
+    pub fn with_queue(mut self, queue: impl TaskQueue<In> + 'static) -> Self {
+        let mut queue: Box<dyn TaskQueue<In>> = Box::new(queue);
+        // `len` only counts what was pushed, where popping could go on forever
+        for _ in 0..self.queue.len() {
+            if let Some(task) = self.queue.pop() {
+                queue.push(task);
+            }
+        }
+
+        self.queue = queue;
+        self
+    }
+
     /// Number of workers currently working
     /// This is the number of workers we haven't tried to stop yet plus the workers that haven't
     /// noticed they were told to stop.
@@ -136,12 +295,71 @@ where
     /// Sets the target number of workers.
     /// Does not stop in-progress workers.
     pub fn set_target_workers(&mut self, n: usize) {
+        self.notify(PoolEvent::TargetChanged { from: self.num_workers, to: n });
         self.num_workers = n;
     }
 
-    /// Add a new task to the back of the queue
+    /// Registers an observer to be told about worker and pool events
+    pub fn add_observer(&mut self, observer: impl PoolObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Cancels any job that runs for longer than `timeout`. Tasks pushed with their own timeout
+    /// use that instead. Cancelled jobs are reported as timed out.
+    pub fn set_job_timeout(&mut self, timeout: Option<Duration>) {
+        self.job_timeout = timeout;
+    }
+
+    /// Discards tasks that have sat in the queue for longer than `timeout` without being
+    /// started. Discarded tasks are counted as expired.
+    pub fn set_queue_timeout(&mut self, timeout: Option<Duration>) {
+        self.queue_timeout = timeout;
+    }
+
+    /// Limits every job in the pool to a combined `rate` per second, with bursts of up to
+    /// `burst`. `None` removes the limit.
+    pub fn set_rate_limit(&mut self, rate: Option<f32>, burst: usize) {
+        self.limiter.set_burst(burst);
+        self.limiter.set_rate(rate);
+    }
+
+    /// A handle to the rate limiter shared by the pool's jobs
+    pub fn rate_limiter(&self) -> RateLimiter {
+        self.limiter.clone()
+    }
+
+    /// Switches the pool to open-loop mode.
+    ///
+    /// Rather than keeping a fixed number of workers busy, the pool starts one queued task per
+    /// scheduled arrival, regardless of whether earlier tasks have finished. The target worker
+    /// count becomes a cap on how many may run at once; if an arrival comes due while the pool
+    /// is at the cap it waits for a free worker, and the delay is recorded in
+    /// `PoolMetrics::start_lag`. Jobs can see when they were meant to start in `Job::scheduled`.
+    ///
+    /// The schedule starts over each time `work` is called.
+    pub fn set_open_loop(&mut self, arrivals: Arrivals, max_workers: usize) {
+        self.open_loop = Some(arrivals);
+        self.set_target_workers(max_workers);
+    }
+
+    /// Add a new task to the queue
     pub fn push(&mut self, task: In) {
-        self.queue.push_back(task);
+        self.queue.push(Queued::new(task));
+    }
+
+    /// Add a new task to the queue with its own deadline or timeout
+    pub fn push_queued(&mut self, task: Queued<In>) {
+        self.queue.push(task);
+    }
+
+    /// Snapshot of the pool's counters and gauges
+    pub fn metrics(&self) -> PoolMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// A handle for reading metrics from another task while `work` is running
+    pub fn metrics_handle(&self) -> MetricsHandle {
+        self.metrics.clone()
     }
 
     /// Attempts to grab any immediately available results from the workers
@@ -158,6 +376,9 @@ where
     }
 
     pub async fn work(&mut self) {
+        self.metrics.restart_clock();
+        self.arrival_clock = self.open_loop.clone().map(|a| ArrivalClock::new(a, Instant::now()));
+
         task::block_on(async {
             loop {
                 self.flush_output().await;
@@ -167,12 +388,21 @@ where
                 }
 
                 self.balance_workers().await;
+                self.update_gauges();
 
-                if !self.working() {
+                if self.finished() {
                     break;
                 }
             }
-        })
+
+            // the last workers may have sent results after this tick's flush
+            self.flush_output().await;
+        });
+
+        // slots still running a job after a stop are torn down once it reports back
+        for context in self.idle_contexts.drain(..) {
+            (self.context_teardown)(context);
+        }
     }
 
     /// Processes outstanding command and worker events
@@ -180,29 +410,72 @@ where
     fn event_loop(&mut self) -> bool {
         while let Ok(event) = self.worker_events.1.try_recv() {
             match event {
-                WorkerEvent::WorkerDone => {
+                WorkerEvent::Done { worker, lifetime } => {
                     self.cur_workers -= 1;
+                    self.withdraw_stop();
+                    self.release_context(worker);
+                    self.metrics.update(|m| {
+                        m.jobs_completed += 1;
+                        m.worker_lifetimes.record(lifetime);
+                    });
+                    self.notify(PoolEvent::WorkerDone { worker, lifetime });
                 }
-                WorkerEvent::WorkerStopped => {
+                WorkerEvent::Stopped { worker, lifetime } => {
                     self.cur_workers -= 1;
-                    self.outstanding_stops -= 1;
+                    self.outstanding_stops = self.outstanding_stops.saturating_sub(1);
+                    self.teardown_context(worker);
+                    self.metrics.update(|m| {
+                        m.jobs_stopped += 1;
+                        m.worker_lifetimes.record(lifetime);
+                    });
+                    self.notify(PoolEvent::WorkerStopped { worker, lifetime });
+                }
+                WorkerEvent::Failed { worker, lifetime } => {
+                    self.cur_workers -= 1;
+                    self.withdraw_stop();
+                    self.teardown_context(worker);
+                    self.metrics.update(|m| {
+                        m.jobs_failed += 1;
+                        m.worker_lifetimes.record(lifetime);
+                    });
+                    self.notify(PoolEvent::WorkerFailed { worker, lifetime });
+                }
+                WorkerEvent::TimedOut { worker, lifetime } => {
+                    self.cur_workers -= 1;
+                    self.withdraw_stop();
+                    self.teardown_context(worker);
+                    self.metrics.update(|m| {
+                        m.jobs_timed_out += 1;
+                        m.worker_lifetimes.record(lifetime);
+                    });
+                    self.notify(PoolEvent::WorkerTimedOut { worker, lifetime });
                 }
             }
         }
 
         while let Ok(command) = self.command_events.1.try_recv() {
+            self.notify(PoolEvent::CommandReceived(command));
+
             match command {
                 WorkerPoolCommand::Stop => {
                     return false;
                 }
+                WorkerPoolCommand::Drain => {
+                    self.draining = true;
+                    self.set_target_workers(0);
+                }
                 WorkerPoolCommand::SetWorkerCount(n) => {
                     let n = match n {
                         0 => 1,
                         n => n,
                     };
 
-                    println!("{}, {}", n, self.num_workers);
-                    self.num_workers = n;
+                    if n != self.num_workers && !self.draining {
+                        self.set_target_workers(n);
+                    }
+                }
+                WorkerPoolCommand::SetRateLimit(rate) => {
+                    self.limiter.set_rate(rate);
                 }
             }
         }
@@ -218,56 +491,218 @@ where
     /// is the "lazy" property of async we wanted to achieve.
     async fn flush_output(&mut self) {
         while let Ok(out) = self.results_channel.1.try_recv() {
+            let full = self.output.is_full();
+            let start = Instant::now();
             self.output.send(out).await;
+            let waited = start.elapsed();
+
+            self.metrics.update(|m| {
+                m.results_emitted += 1;
+                m.output_wait += waited;
+            });
+
+            if full {
+                self.notify(PoolEvent::OutputBackpressure { waited });
+            }
+        }
+    }
+
+    fn notify(&mut self, event: PoolEvent) {
+        for observer in self.observers.iter_mut() {
+            observer.on_event(&event);
         }
     }
 
-    /// Starts a new worker if there is work to do
-    fn start_worker(&mut self) {
-        if self.queue.is_empty() {
-            return;
+    /// Copy the pool's current state into the metrics gauges
+    fn update_gauges(&self) {
+        let queue_depth = self.queue.len();
+        let cur_workers = self.cur_workers();
+        let target_workers = self.target_workers();
+
+        self.metrics.update(|m| {
+            m.queue_depth = queue_depth;
+            m.cur_workers = cur_workers;
+            m.target_workers = target_workers;
+        });
+    }
+
+    /// Whether there's nothing left to do. A draining pool waits for every worker to actually
+    /// finish, including the ones already asked to stop.
+    fn finished(&self) -> bool {
+        if self.draining {
+            return self.cur_workers == 0;
         }
 
-        let task = self.queue.pop_front().unwrap();
+        !self.working() && !self.awaiting_arrivals()
+    }
+
+    /// Whether an open-loop pool still has tasks scheduled to start
+    fn awaiting_arrivals(&self) -> bool {
+        let scheduled = self.arrival_clock.as_ref().and_then(|c| c.next_arrival()).is_some();
+        scheduled && !self.queue.is_empty()
+    }
+
+    /// Starts a new worker if there is work to do.
+    /// `scheduled` is when an open-loop pool meant to start it.
+    /// Returns whether a worker was started.
+    fn start_worker(&mut self, scheduled: Option<Instant>) -> bool {
+        let queued = match self.next_task() {
+            Some(queued) => queued,
+            None => return false,
+        };
+        let task = queued.task;
+        let timeout = queued.timeout.or(self.job_timeout);
+
+        let worker = self.next_worker_id;
         let work_send = self.results_channel.0.clone();
         let close_recv = self.close_channel.1.clone();
         let event_send = self.worker_events.0.clone();
-        let job = Job::new(task, close_recv, work_send);
+        let context = self.idle_contexts.pop().unwrap_or_else(self.context_init);
+        let mut job = Job::with_context(task, close_recv, work_send, context.clone());
+        job.limiter = self.limiter.clone();
+        job.scheduled = scheduled;
         let fut = (self.task)(job);
+        self.active_contexts.insert(worker, context);
 
         // If a worker stops on its own without us telling it to stop then we want to know about
         // it so that we can spin up a replacement. This is done through an unbounded crossbeam
         // channnel that is processed every tick to update state.
+        //
+        // Timeouts cancel the job by dropping its future.
         async_std::task::spawn(async move {
-            let status = fut.await;
+            let started = Instant::now();
+            let status = match timeout {
+                Some(timeout) => future::timeout(timeout, fut).await.ok(),
+                None => Some(fut.await),
+            };
+            let lifetime = started.elapsed();
             let message = match status {
-                JobStatus::Done => WorkerEvent::WorkerDone,
-                JobStatus::Stopped => WorkerEvent::WorkerStopped,
-                JobStatus::Running => panic!("this shouldn't happen"),
+                Some(JobStatus::Done) => WorkerEvent::Done { worker, lifetime },
+                Some(JobStatus::Stopped) => WorkerEvent::Stopped { worker, lifetime },
+                Some(JobStatus::Failed) => WorkerEvent::Failed { worker, lifetime },
+                Some(JobStatus::Running) => panic!("this shouldn't happen"),
+                None => WorkerEvent::TimedOut { worker, lifetime },
             };
 
             event_send.send(message).expect("failed to send WorkerEvent");
         });
 
         self.cur_workers += 1;
+        self.next_worker_id += 1;
+        let lag = scheduled.map(|scheduled| scheduled.elapsed());
+        self.metrics.update(|m| {
+            m.jobs_started += 1;
+            if let Some(lag) = lag {
+                m.start_lag.record(lag);
+            }
+        });
+        self.notify(PoolEvent::WorkerStarted { worker });
+
+        true
+    }
+
+    /// A worker finished on its own, so its slot (and context) can be reused by the next job.
+    /// Slots beyond what the target worker count needs are torn down instead.
+    fn release_context(&mut self, worker: usize) {
+        if let Some(context) = self.active_contexts.remove(&worker) {
+            if self.cur_workers() + self.idle_contexts.len() < self.target_workers() {
+                self.idle_contexts.push(context);
+            } else {
+                (self.context_teardown)(context);
+            }
+        }
+    }
+
+    /// A worker's slot is going away; stopped workers aren't replaced, and failed or timed out
+    /// workers may have left their context in a bad state.
+    fn teardown_context(&mut self, worker: usize) {
+        if let Some(context) = self.active_contexts.remove(&worker) {
+            (self.context_teardown)(context);
+        }
+    }
+
+    /// Pops the next task that hasn't expired, discarding any that have
+    fn next_task(&mut self) -> Option<Queued<In>> {
+        while let Some(queued) = self.queue.pop() {
+            let now = Instant::now();
+            if !queued.expired(now, self.queue_timeout) {
+                return Some(queued);
+            }
+
+            let waited = now.duration_since(queued.queued_at);
+            self.metrics.update(|m| m.tasks_expired += 1);
+            self.notify(PoolEvent::TaskExpired { waited });
+        }
+
+        None
     }
 
     /// Find a listening worker and tell it to stop.
     /// Doesn't forcibly kill in-progress tasks.
     async fn send_stop_work_message(&mut self) {
         self.outstanding_stops += 1;
+        self.notify(PoolEvent::StopRequested);
         self.close_channel.0.send(()).await;
     }
 
+    /// A worker went away without being stopped, which leaves one stop request more than
+    /// needed. Takes back one that no worker has claimed yet, so it can't stop a worker started
+    /// later; if they've all been claimed, those workers will report as stopped.
+    fn withdraw_stop(&mut self) {
+        if self.outstanding_stops > 0 && self.close_channel.1.try_recv().is_ok() {
+            self.outstanding_stops -= 1;
+        }
+    }
+
     /// Pops tasks from the queue if we have available worker capacity
     /// Sends out messages if any of our workers have delivered results
     pub async fn balance_workers(&mut self) {
-        if self.cur_workers() < self.target_workers() {
-            self.start_worker();
+        if self.arrival_clock.is_some() {
+            self.dispatch_arrivals();
+        } else if self.cur_workers() < self.target_workers() {
+            self.start_worker(None);
         } else if self.cur_workers() > self.target_workers() {
             self.send_stop_work_message().await;
         }
     }
+
+    /// Starts a worker for every arrival that has come due, as long as we're under the cap.
+    /// Open-loop jobs run to completion, so there's never a reason to stop one early.
+    fn dispatch_arrivals(&mut self) {
+        let now = Instant::now();
+
+        while let Some(scheduled) = self.arrival_clock.as_ref().and_then(|c| c.next_arrival()) {
+            if scheduled > now || self.cur_workers() >= self.target_workers() {
+                break;
+            }
+
+            if !self.start_worker(Some(scheduled)) {
+                break;
+            }
+
+            if let Some(clock) = self.arrival_clock.as_mut() {
+                clock.advance();
+            }
+        }
+    }
+}
+
+impl<In, Out, F, Ctx> Drop for WorkerPool<In, Out, F, Ctx> {
+    /// Tears down the slots of jobs that finished after the pool was stopped
+    fn drop(&mut self) {
+        while let Ok(event) = self.worker_events.1.try_recv() {
+            let worker = match event {
+                WorkerEvent::Done { worker, .. }
+                | WorkerEvent::Stopped { worker, .. }
+                | WorkerEvent::Failed { worker, .. }
+                | WorkerEvent::TimedOut { worker, .. } => worker,
+            };
+
+            if let Some(context) = self.active_contexts.remove(&worker) {
+                (self.context_teardown)(context);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -278,12 +713,12 @@ mod tests {
     use std::time::Duration;
 
     /// Double the input some number of times or until we receive a close message
-    async fn double(job: Job<(usize, usize), usize>) {
+    async fn double(job: Job<(usize, usize), usize>) -> JobStatus {
         let (mut i, n) = job.task;
         for _ in 0..n {
             // play nice with the pool by allowing it to stop this loop early
             if job.stop_requested() {
-                break;
+                return JobStatus::Stopped;
             }
 
             // do the actual work
@@ -295,6 +730,8 @@ mod tests {
             // pretend this is hard
             task::sleep(Duration::from_millis(100)).await;
         }
+
+        JobStatus::Done
     }
 
     #[async_test]
@@ -316,4 +753,373 @@ mod tests {
 
         pool.work().await;
     }
+
+    #[async_test]
+    async fn pool_metrics() {
+        let (send, recv) = channel(4);
+        let mut pool = WorkerPool::new(double, send, 2);
+
+        pool.push((1, 3));
+        pool.push((2, 3));
+        pool.push((3, 3));
+
+        task::spawn(async move { while recv.recv().await.is_ok() {} });
+
+        pool.work().await;
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.jobs_started, 3);
+        assert_eq!(metrics.jobs_completed, 3);
+        assert_eq!(metrics.jobs_in_flight(), 0);
+        assert_eq!(metrics.results_emitted, 9);
+        assert_eq!(metrics.queue_depth, 0);
+        assert_eq!(metrics.worker_lifetimes.count, 3);
+        assert!(metrics.worker_lifetimes.min >= Duration::from_millis(300));
+    }
+
+    #[async_test]
+    async fn pool_observer() {
+        use std::sync::{Arc, Mutex};
+
+        let (send, recv) = channel(4);
+        let mut pool = WorkerPool::new(double, send, 2);
+        let events = Arc::new(Mutex::new(vec![]));
+        let recorded = events.clone();
+        pool.add_observer(move |event: &PoolEvent| recorded.lock().unwrap().push(*event));
+
+        pool.push((1, 2));
+        pool.push((2, 2));
+        pool.command_channel().send(WorkerPoolCommand::SetWorkerCount(3)).unwrap();
+
+        task::spawn(async move { while recv.recv().await.is_ok() {} });
+
+        pool.work().await;
+
+        let events = events.lock().unwrap();
+        let count = |f: fn(&PoolEvent) -> bool| events.iter().filter(|e| f(e)).count();
+        assert_eq!(count(|e| matches!(e, PoolEvent::WorkerStarted { .. })), 2);
+        assert_eq!(count(|e| matches!(e, PoolEvent::WorkerDone { .. })), 2);
+        assert!(events.contains(&PoolEvent::TargetChanged { from: 2, to: 3 }));
+        assert!(events.contains(&PoolEvent::CommandReceived(WorkerPoolCommand::SetWorkerCount(3))));
+    }
+
+    #[async_test]
+    async fn pool_timeouts() {
+        let (send, recv) = channel(4);
+        let mut pool = WorkerPool::new(double, send, 2);
+        pool.set_job_timeout(Some(Duration::from_millis(150)));
+
+        // (1, 1) finishes in time, (1, 10) would take a second
+        pool.push((1, 1));
+        pool.push((1, 10));
+        pool.push_queued(Queued::new((1, 1)).with_deadline(Instant::now()));
+
+        task::spawn(async move { while recv.recv().await.is_ok() {} });
+
+        pool.work().await;
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.jobs_started, 2);
+        assert_eq!(metrics.jobs_completed, 1);
+        assert_eq!(metrics.jobs_timed_out, 1);
+        assert_eq!(metrics.tasks_expired, 1);
+    }
+
+    #[async_test]
+    async fn pool_rate_limit() {
+        async fn limited(job: Job<usize, ()>) -> JobStatus {
+            for _ in 0..job.task {
+                job.limiter.acquire().await;
+                job.results.send(()).await;
+            }
+
+            JobStatus::Done
+        }
+
+        let (send, recv) = channel(4);
+        let mut pool = WorkerPool::new(limited, send, 4);
+        pool.set_rate_limit(Some(1000.0), 1);
+        pool.command_channel().send(WorkerPoolCommand::SetRateLimit(Some(200.0))).unwrap();
+
+        for _ in 0..4 {
+            pool.push(5);
+        }
+
+        task::spawn(async move { while recv.recv().await.is_ok() {} });
+
+        let start = Instant::now();
+        pool.work().await;
+
+        // 20 results shared across four workers at 200/s
+        assert_eq!(pool.rate_limiter().rate(), Some(200.0));
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[async_test]
+    async fn pool_open_loop() {
+        async fn sleepy(job: Job<u64, Instant>) -> JobStatus {
+            task::sleep(Duration::from_millis(job.task)).await;
+            job.results.send(job.scheduled.unwrap()).await;
+            JobStatus::Done
+        }
+
+        let (send, recv) = channel(16);
+        let mut pool = WorkerPool::new(sleepy, send, 1);
+        pool.set_open_loop(Arrivals::Constant(100.0), 8);
+
+        // arrivals every 10ms no matter how long each task takes
+        for _ in 0..8 {
+            pool.push(30);
+        }
+
+        let start = Instant::now();
+        pool.work().await;
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.jobs_started, 8);
+        assert_eq!(metrics.start_lag.count, 8);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_millis(400));
+
+        let mut scheduled = vec![];
+        while let Ok(at) = recv.try_recv() {
+            scheduled.push(at);
+        }
+        assert_eq!(scheduled.len(), 8);
+    }
+
+    #[async_test]
+    async fn pool_open_loop_at_cap() {
+        async fn sleepy(job: Job<u64, ()>) -> JobStatus {
+            task::sleep(Duration::from_millis(job.task)).await;
+            JobStatus::Done
+        }
+
+        let (send, _recv) = channel(1);
+        let mut pool = WorkerPool::new(sleepy, send, 1);
+        pool.set_open_loop(Arrivals::Constant(100.0), 1);
+
+        for _ in 0..3 {
+            pool.push(50);
+        }
+
+        pool.work().await;
+
+        // with a single worker, each arrival waits for the last 50ms task to finish
+        let metrics = pool.metrics();
+        assert_eq!(metrics.jobs_started, 3);
+        assert!(metrics.start_lag.max >= Duration::from_millis(60));
+    }
+
+    #[async_test]
+    async fn pool_context() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        static INITS: AtomicUsize = AtomicUsize::new(0);
+        static TEARDOWNS: AtomicUsize = AtomicUsize::new(0);
+
+        async fn count_jobs(job: Job<(), (), Arc<AtomicUsize>>) -> JobStatus {
+            job.context.fetch_add(1, Ordering::SeqCst);
+            job.results.send(()).await;
+            JobStatus::Done
+        }
+
+        let (send, recv) = channel(4);
+        let mut pool = WorkerPool::with_context(
+            count_jobs,
+            send,
+            1,
+            || {
+                INITS.fetch_add(1, Ordering::SeqCst);
+                Arc::new(AtomicUsize::new(0))
+            },
+            |jobs| {
+                assert_eq!(jobs.load(Ordering::SeqCst), 3);
+                TEARDOWNS.fetch_add(1, Ordering::SeqCst);
+            },
+        );
+
+        for _ in 0..3 {
+            pool.push(());
+        }
+
+        task::spawn(async move { while recv.recv().await.is_ok() {} });
+
+        pool.work().await;
+
+        assert_eq!(INITS.load(Ordering::SeqCst), 1);
+        assert_eq!(TEARDOWNS.load(Ordering::SeqCst), 1);
+    }
+
+    #[async_test]
+    async fn pool_context_stopped() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static INITS: AtomicUsize = AtomicUsize::new(0);
+        static TEARDOWNS: AtomicUsize = AtomicUsize::new(0);
+
+        async fn slow(_: Job<(), (), ()>) -> JobStatus {
+            task::sleep(Duration::from_millis(500)).await;
+            JobStatus::Done
+        }
+
+        let (send, _recv) = channel(4);
+        let mut pool = WorkerPool::with_context(
+            slow,
+            send,
+            2,
+            || {
+                INITS.fetch_add(1, Ordering::SeqCst);
+            },
+            |_| {
+                TEARDOWNS.fetch_add(1, Ordering::SeqCst);
+            },
+        );
+        let command = pool.command_channel();
+        pool.push(());
+        pool.push(());
+
+        task::spawn(async move {
+            task::sleep(Duration::from_millis(20)).await;
+            command.send(WorkerPoolCommand::Stop).unwrap();
+        });
+
+        // both slots are still running when the pool stops, and are torn down once their jobs
+        // are done with them
+        pool.work().await;
+        assert_eq!(INITS.load(Ordering::SeqCst), 2);
+        assert_eq!(TEARDOWNS.load(Ordering::SeqCst), 0);
+
+        task::sleep(Duration::from_millis(600)).await;
+        drop(pool);
+        assert_eq!(TEARDOWNS.load(Ordering::SeqCst), 2);
+    }
+
+    #[async_test]
+    async fn pool_drain() {
+        let (send, recv) = channel(64);
+        let mut pool = WorkerPool::new(double, send, 2);
+        let command = pool.command_channel();
+
+        pool.push((1, 100));
+        pool.push((1, 100));
+        pool.push((1, 100));
+
+        let results = task::spawn(async move {
+            let mut count = 0;
+            while recv.recv().await.is_ok() {
+                count += 1;
+                if count == 4 {
+                    command.send(WorkerPoolCommand::Drain).unwrap();
+                }
+            }
+            count
+        });
+
+        pool.work().await;
+        let metrics = pool.metrics();
+        drop(pool);
+
+        // both running jobs were asked to stop, and the third task never started
+        assert_eq!(metrics.jobs_started, 2);
+        assert_eq!(metrics.jobs_stopped, 2);
+        assert_eq!(metrics.cur_workers, 0);
+        assert!(results.await < 10);
+    }
+
+    /// Ignores stop requests, sleeps for `task` milliseconds, then fails
+    async fn stubborn(job: Job<u64, ()>) -> JobStatus {
+        task::sleep(Duration::from_millis(job.task)).await;
+        JobStatus::Failed
+    }
+
+    #[async_test]
+    async fn pool_drain_failing() {
+        let (send, _recv) = channel(4);
+        let mut pool = WorkerPool::new(stubborn, send, 2);
+        let command = pool.command_channel();
+        pool.push(100);
+        pool.push(100);
+
+        task::spawn(async move {
+            task::sleep(Duration::from_millis(20)).await;
+            command.send(WorkerPoolCommand::Drain).unwrap();
+        });
+
+        // both jobs are asked to stop, then fail without ever claiming the stop
+        pool.work().await;
+        let metrics = pool.metrics();
+        assert_eq!(metrics.jobs_failed, 2);
+        assert_eq!(metrics.cur_workers, 0);
+    }
+
+    #[async_test]
+    async fn pool_scale_down_and_up() {
+        /// Sleeps for some milliseconds, stopping early if asked and listening for it
+        async fn nap(job: Job<(u64, bool), ()>) -> JobStatus {
+            let (millis, listening) = job.task;
+            if !listening {
+                task::sleep(Duration::from_millis(millis)).await;
+                return JobStatus::Done;
+            }
+
+            match job.sleep(Duration::from_millis(millis)).await {
+                true => JobStatus::Stopped,
+                false => JobStatus::Done,
+            }
+        }
+
+        let (send, _recv) = channel(4);
+        let mut pool = WorkerPool::new(nap, send, 4);
+        let command = pool.command_channel();
+        let metrics = pool.metrics_handle();
+        for _ in 0..4 {
+            pool.push((50, false));
+        }
+        for _ in 0..8 {
+            pool.push((1000, true));
+        }
+
+        let running = task::spawn(async move {
+            task::sleep(Duration::from_millis(20)).await;
+            command.send(WorkerPoolCommand::SetWorkerCount(2)).unwrap();
+            task::sleep(Duration::from_millis(100)).await;
+            command.send(WorkerPoolCommand::SetWorkerCount(4)).unwrap();
+            task::sleep(Duration::from_millis(100)).await;
+            let running = metrics.snapshot().cur_workers;
+            command.send(WorkerPoolCommand::Stop).unwrap();
+            running
+        });
+
+        // the first jobs finish without claiming the stops sent to scale down, which mustn't be
+        // left around to stop the jobs started after them
+        pool.work().await;
+        assert_eq!(running.await, 4);
+        assert_eq!(pool.metrics().jobs_stopped, 0);
+        assert_eq!(pool.metrics().jobs_started, 8);
+    }
+
+    #[async_test]
+    async fn pool_drain_timing_out() {
+        let (send, _recv) = channel(4);
+        let mut pool = WorkerPool::new(stubborn, send, 2);
+        pool.set_job_timeout(Some(Duration::from_millis(100)));
+        let command = pool.command_channel();
+        pool.push(1000);
+        pool.push(1000);
+
+        task::spawn(async move {
+            task::sleep(Duration::from_millis(20)).await;
+            command.send(WorkerPoolCommand::Drain).unwrap();
+        });
+
+        // both jobs are asked to stop, then cancelled without ever claiming the stop
+        pool.work().await;
+        let metrics = pool.metrics();
+        assert_eq!(metrics.jobs_timed_out, 2);
+        assert_eq!(metrics.cur_workers, 0);
+    }
 }
@@ -0,0 +1,344 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// A task waiting in a `WorkerPool`'s queue, along with when it got there and any limits on
+/// how long it may wait or run.
+#[derive(Debug, Clone)]
+pub struct Queued<T> {
+    pub task: T,
+    /// When the task was queued
+    pub queued_at: Instant,
+    /// Discard the task unstarted if it's still waiting at this point
+    pub deadline: Option<Instant>,
+    /// Cancel the task if it runs for longer than this. Overrides the pool's job timeout.
+    pub timeout: Option<Duration>,
+}
+
+impl<T> Queued<T> {
+    pub fn new(task: T) -> Self {
+        Self { task, queued_at: Instant::now(), deadline: None, timeout: None }
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Whether the task has passed its deadline, or has been waiting longer than `max_wait`
+    pub fn expired(&self, now: Instant, max_wait: Option<Duration>) -> bool {
+        let past_deadline = match self.deadline {
+            Some(deadline) => now >= deadline,
+            None => false,
+        };
+        let waited_too_long = match max_wait {
+            Some(max_wait) => now.duration_since(self.queued_at) >= max_wait,
+            None => false,
+        };
+
+        past_deadline || waited_too_long
+    }
+}
+
+/// # TaskQueue
+///
+/// The discipline a `WorkerPool` uses to decide which waiting task gets the next free worker.
+///
+/// Pools use `Fifo` unless told otherwise with `WorkerPool::with_queue`. Anything that can
+/// hold tasks and hand them back one at a time can be a queue, so if none of the provided
+/// disciplines fit you can bring your own.
+///
+/// Tasks come wrapped in `Queued`, which the pool uses for timeouts and deadlines; disciplines
+/// make their decisions based on the `task` inside.
+pub trait TaskQueue<T>: Send {
+    /// Adds a task to the queue
+    fn push(&mut self, task: Queued<T>);
+
+    /// Removes the task that should run next
+    fn pop(&mut self) -> Option<Queued<T>>;
+
+    /// Number of tasks waiting
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// First in, first out. The default.
+pub struct Fifo<T> {
+    tasks: VecDeque<Queued<T>>,
+}
+
+impl<T> Fifo<T> {
+    pub fn new() -> Self {
+        Self { tasks: VecDeque::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { tasks: VecDeque::with_capacity(capacity) }
+    }
+}
+
+impl<T> Default for Fifo<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send> TaskQueue<T> for Fifo<T> {
+    fn push(&mut self, task: Queued<T>) {
+        self.tasks.push_back(task);
+    }
+
+    fn pop(&mut self) -> Option<Queued<T>> {
+        self.tasks.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.tasks.len()
+    }
+}
+
+/// Last in, first out.
+///
+/// Useful for latency-sensitive work where a fresh task is worth more than a stale one; under
+/// load the oldest tasks wait the longest, but the newest get served quickly.
+pub struct Lifo<T> {
+    tasks: Vec<Queued<T>>,
+}
+
+impl<T> Lifo<T> {
+    pub fn new() -> Self {
+        Self { tasks: vec![] }
+    }
+}
+
+impl<T> Default for Lifo<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Send> TaskQueue<T> for Lifo<T> {
+    fn push(&mut self, task: Queued<T>) {
+        self.tasks.push(task);
+    }
+
+    fn pop(&mut self) -> Option<Queued<T>> {
+        self.tasks.pop()
+    }
+
+    fn len(&self) -> usize {
+        self.tasks.len()
+    }
+}
+
+/// Strict priority levels. Higher priorities always go first, and tasks with the same priority
+/// are served in the order they arrived.
+///
+/// The priority is read from the task itself, so tasks are pushed the same way as always:
+/// ```
+/// use clobber::{PriorityQueue, Queued, TaskQueue};
+///
+/// // (priority, name)
+/// let mut queue = PriorityQueue::new(|task: &(usize, &str)| task.0);
+/// queue.push(Queued::new((0, "bulk")));
+/// queue.push(Queued::new((9, "canary")));
+///
+/// assert_eq!(queue.pop().unwrap().task, (9, "canary"));
+/// ```
+pub struct PriorityQueue<T> {
+    priority: fn(&T) -> usize,
+    levels: BTreeMap<usize, VecDeque<Queued<T>>>,
+    len: usize,
+}
+
+impl<T> PriorityQueue<T> {
+    pub fn new(priority: fn(&T) -> usize) -> Self {
+        Self { priority, levels: BTreeMap::new(), len: 0 }
+    }
+}
+
+impl<T: Send> TaskQueue<T> for PriorityQueue<T> {
+    fn push(&mut self, task: Queued<T>) {
+        let priority = (self.priority)(&task.task);
+        self.levels.entry(priority).or_default().push_back(task);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Queued<T>> {
+        let (&priority, level) = self.levels.iter_mut().next_back()?;
+        let task = level.pop_front();
+
+        if level.is_empty() {
+            self.levels.remove(&priority);
+        }
+
+        self.len -= 1;
+        task
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Weighted fair queuing across tenant keys.
+///
+/// Each tenant gets its own FIFO queue, and workers are shared out between the tenants that
+/// have work waiting in proportion to their weights. A tenant with weight 3 gets three tasks
+/// started for every one started for a tenant with weight 1, no matter how many tasks either
+/// of them has queued. Tenants without an explicit weight get the default weight.
+///
+/// Tenants are picked with smooth weighted round robin, so a heavy tenant's turns are spread
+/// out rather than handed over in one burst.
+pub struct FairQueue<T, K> {
+    key: fn(&T) -> K,
+    weights: HashMap<K, usize>,
+    default_weight: usize,
+    tenants: Vec<Tenant<T, K>>,
+    len: usize,
+}
+
+struct Tenant<T, K> {
+    key: K,
+    weight: i64,
+    current: i64,
+    tasks: VecDeque<Queued<T>>,
+}
+
+impl<T, K: Hash + Eq> FairQueue<T, K> {
+    pub fn new(key: fn(&T) -> K) -> Self {
+        Self { key, weights: HashMap::new(), default_weight: 1, tenants: vec![], len: 0 }
+    }
+
+    /// Sets the weight for one tenant. Weights of zero are treated as one.
+    pub fn with_weight(mut self, key: K, weight: usize) -> Self {
+        let weight = weight.max(1);
+        if let Some(tenant) = self.tenants.iter_mut().find(|t| t.key == key) {
+            tenant.weight = weight as i64;
+        }
+
+        self.weights.insert(key, weight);
+        self
+    }
+
+    /// Sets the weight for tenants that weren't given one explicitly.
+    pub fn with_default_weight(mut self, weight: usize) -> Self {
+        self.default_weight = weight.max(1);
+        self
+    }
+}
+
+impl<T: Send, K: Hash + Eq + Send> TaskQueue<T> for FairQueue<T, K> {
+    fn push(&mut self, task: Queued<T>) {
+        let key = (self.key)(&task.task);
+
+        match self.tenants.iter_mut().find(|t| t.key == key) {
+            Some(tenant) => tenant.tasks.push_back(task),
+            None => {
+                let weight = *self.weights.get(&key).unwrap_or(&self.default_weight) as i64;
+                let mut tasks = VecDeque::new();
+                tasks.push_back(task);
+                self.tenants.push(Tenant { key, weight, current: 0, tasks });
+            }
+        }
+
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Queued<T>> {
+        let mut total = 0;
+        let mut selected: Option<(usize, i64)> = None;
+
+        for (i, tenant) in self.tenants.iter_mut().enumerate() {
+            if tenant.tasks.is_empty() {
+                continue;
+            }
+
+            tenant.current += tenant.weight;
+            total += tenant.weight;
+
+            match selected {
+                Some((_, best)) if best >= tenant.current => {}
+                _ => selected = Some((i, tenant.current)),
+            }
+        }
+
+        let tenant = &mut self.tenants[selected?.0];
+        tenant.current -= total;
+        self.len -= 1;
+        tenant.tasks.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain<T>(queue: &mut dyn TaskQueue<T>) -> Vec<T> {
+        std::iter::from_fn(|| queue.pop()).map(|queued| queued.task).collect()
+    }
+
+    #[test]
+    fn fifo_and_lifo() {
+        let mut fifo = Fifo::new();
+        let mut lifo = Lifo::new();
+        for i in 0..3 {
+            fifo.push(Queued::new(i));
+            lifo.push(Queued::new(i));
+        }
+
+        assert_eq!(drain(&mut fifo), vec![0, 1, 2]);
+        assert_eq!(drain(&mut lifo), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn priority_levels() {
+        let mut queue = PriorityQueue::new(|task: &(usize, usize)| task.0);
+        for &task in &[(1, 0), (5, 1), (1, 2), (5, 3)] {
+            queue.push(Queued::new(task));
+        }
+        assert_eq!(queue.len(), 4);
+
+        let order: Vec<usize> = drain(&mut queue).into_iter().map(|t| t.1).collect();
+        assert_eq!(order, vec![1, 3, 0, 2]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn weighted_fairness() {
+        let mut queue = FairQueue::new(|task: &(&str, usize)| task.0).with_weight("heavy", 3);
+        for i in 0..8 {
+            queue.push(Queued::new(("heavy", i)));
+            queue.push(Queued::new(("light", i)));
+        }
+
+        let first: Vec<&str> = drain(&mut queue).into_iter().take(8).map(|t| t.0).collect();
+        let heavy = first.iter().filter(|&&k| k == "heavy").count();
+        assert_eq!(heavy, 6);
+        assert_eq!(first[..4].iter().filter(|&&k| k == "light").count(), 1);
+    }
+
+    #[test]
+    fn expiry() {
+        let now = Instant::now();
+        let queued = Queued::new(()).with_deadline(now + Duration::from_secs(5));
+
+        assert!(!queued.expired(now, None));
+        assert!(queued.expired(now + Duration::from_secs(5), None));
+        assert!(queued.expired(now + Duration::from_secs(1), Some(Duration::from_millis(500))));
+    }
+}
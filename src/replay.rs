@@ -0,0 +1,241 @@
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    future::Future,
+    io::{self, ErrorKind},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    arrivals::Arrivals,
+    http::{HttpRequest, HttpResponse},
+    pool::{JobStatus, WorkerPool},
+};
+
+/// One line of a request log
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LoggedRequest {
+    pub target: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Seconds since the start of the log
+    #[serde(default)]
+    pub timestamp: Option<f64>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+impl LoggedRequest {
+    /// The request as a single-shot `HttpRequest`
+    pub fn to_http(&self) -> io::Result<HttpRequest> {
+        let mut request = HttpRequest::new(&self.method, &self.target)?.with_count(1);
+
+        for (name, value) in self.headers.iter() {
+            request = request.with_header(name, value);
+        }
+
+        if let Some(body) = self.body.as_ref() {
+            request = request.with_body(body.clone().into_bytes());
+        }
+
+        Ok(request)
+    }
+}
+
+/// How fast to replay a log
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pace {
+    /// Start each request as soon as the pool has a worker for it
+    AsFastAsPossible,
+    /// Keep the log's timing, sped up by `speed`: 2.0 replays an hour of traffic in 30 minutes
+    Original { speed: f32 },
+}
+
+/// # RequestLog
+///
+/// A log of HTTP requests, one JSON object per line, to replay through a `WorkerPool` running
+/// `http_worker`. Needs the `json` feature.
+///
+/// ```text
+/// {"target": "http://localhost:8000/login", "method": "POST", "body": "{\"user\": 1}", "timestamp": 0.0}
+/// {"target": "http://localhost:8000/items", "headers": {"Accept": "application/json"}, "timestamp": 0.25}
+/// ```
+///
+/// Only `target` is required. `method` defaults to `GET`, and `timestamp` is in seconds,
+/// relative to whenever the log started.
+///
+/// ```no_run
+/// # use async_std::{sync::channel, task};
+/// use clobber::{http_worker, Pace, RequestLog, WorkerPool};
+/// use std::path::Path;
+///
+/// let log = RequestLog::from_file(Path::new("requests.jsonl")).unwrap();
+///
+/// let (send, _recv) = channel(1024);
+/// let mut pool = WorkerPool::new(http_worker, send, 64);
+/// log.load(&mut pool, Pace::Original { speed: 2.0 }).unwrap();
+///
+/// task::block_on(pool.work());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestLog {
+    requests: Vec<LoggedRequest>,
+}
+
+impl RequestLog {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses a log, one JSON object per line. Blank lines are skipped.
+    pub fn parse(log: &str) -> io::Result<Self> {
+        let mut requests = vec![];
+
+        for (number, line) in log.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let request = serde_json::from_str(line).map_err(|e| {
+                io::Error::new(ErrorKind::InvalidData, format!("line {}: {}", number + 1, e))
+            })?;
+            requests.push(request);
+        }
+
+        Ok(Self { requests })
+    }
+
+    pub fn requests(&self) -> &[LoggedRequest] {
+        &self.requests
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Pushes every request onto `pool`. Replaying with the original timing puts the pool in
+    /// open-loop mode, on a schedule made from the log's timestamps, with the pool's current
+    /// target worker count as the cap on requests in flight.
+    ///
+    /// Fails without pushing anything if a request has a bad target.
+    pub fn load<F, Ctx>(
+        &self,
+        pool: &mut WorkerPool<HttpRequest, HttpResponse, F, Ctx>,
+        pace: Pace,
+    ) -> io::Result<()>
+    where
+        F: Future<Output = JobStatus> + Send + 'static,
+        Ctx: Clone + Send + Sync + 'static,
+    {
+        let mut order: Vec<&LoggedRequest> = self.requests.iter().collect();
+
+        if let Pace::Original { speed } = pace {
+            // requests without a timestamp go out alongside the one before them
+            let mut last = 0.0;
+            let mut timed: Vec<(f64, &LoggedRequest)> = vec![];
+            for request in order.iter() {
+                last = request.timestamp.unwrap_or(last);
+                timed.push((last, request));
+            }
+
+            timed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            let first = timed.first().map_or(0.0, |&(timestamp, _)| timestamp);
+            let speed = match speed {
+                speed if speed > 0.0 => speed as f64,
+                _ => 1.0,
+            };
+
+            let offsets = timed
+                .iter()
+                .map(|&(timestamp, _)| {
+                    Duration::from_secs_f64((timestamp - first).max(0.0) / speed)
+                })
+                .collect();
+            order = timed.into_iter().map(|(_, request)| request).collect();
+
+            let max_workers = pool.target_workers();
+            pool.set_open_loop(Arrivals::Schedule(offsets), max_workers);
+        }
+
+        let requests = order.iter().map(|r| r.to_http()).collect::<io::Result<Vec<_>>>()?;
+        for request in requests {
+            pool.push(request);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http_worker, testing::http_server};
+    use async_std::sync::{channel, Receiver};
+    use futures_await_test::async_test;
+    use std::time::Instant;
+
+    #[test]
+    fn parse() {
+        let log = RequestLog::parse(
+            r#"{"target": "http://a/x", "method": "POST", "headers": {"X-A": "1"}, "body": "hi", "timestamp": 1.5}
+
+               {"target": "http://a/y"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.requests()[0].method, "POST");
+        assert_eq!(log.requests()[0].timestamp, Some(1.5));
+        assert_eq!(log.requests()[1].method, "GET");
+        assert_eq!(log.requests()[1].body, None);
+
+        let error =
+            RequestLog::parse("{\"target\": \"http://a\"}\n{\"method\": \"GET\"}").unwrap_err();
+        assert!(error.to_string().starts_with("line 2"), "{}", error);
+    }
+
+    /// Replies 204 to every request
+    async fn server() -> String {
+        let no_content: &[u8] = b"HTTP/1.1 204 No Content\r\n\r\n";
+        http_server(move |_| no_content, Default::default()).await
+    }
+
+    #[async_test]
+    async fn original_timing() {
+        let addr = server().await;
+        let log: String = [0.4, 0.0, 0.2]
+            .iter()
+            .map(|t| format!("{{\"target\": \"http://{}/\", \"timestamp\": {}}}\n", addr, t))
+            .collect();
+        let log = RequestLog::parse(&log).unwrap();
+
+        let (send, recv): (_, Receiver<HttpResponse>) = channel(16);
+        let mut pool = WorkerPool::new(http_worker, send, 4);
+        log.load(&mut pool, Pace::Original { speed: 2.0 }).unwrap();
+
+        let start = Instant::now();
+        pool.work().await;
+
+        // the last request was 0.4s in, replayed at double speed
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_millis(400));
+
+        let mut statuses = vec![];
+        while let Ok(response) = recv.try_recv() {
+            statuses.push(response.status);
+        }
+        assert_eq!(statuses, vec![Some(204); 3]);
+    }
+}
@@ -0,0 +1,398 @@
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "json")]
+use std::io::{self, ErrorKind};
+
+use crate::{histogram::Histogram, metrics::PoolMetrics, pid::PidController, sample::Sample};
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Latency figures, in milliseconds
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct LatencySummary {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+}
+
+impl From<&Histogram> for LatencySummary {
+    fn from(histogram: &Histogram) -> Self {
+        if histogram.is_empty() {
+            return Self::default();
+        }
+
+        let percentiles = histogram.percentiles();
+        Self {
+            mean: ms(histogram.mean()),
+            p50: ms(percentiles.p50),
+            p90: ms(percentiles.p90),
+            p99: ms(percentiles.p99),
+            p999: ms(percentiles.p999),
+            max: ms(percentiles.max),
+        }
+    }
+}
+
+/// One row of a report's timeline, covering the time since the row before it
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct ReportTick {
+    /// Seconds since the start of the run
+    pub at: f64,
+    pub results: u64,
+    pub failures: u64,
+    /// Results per second
+    pub rate: f32,
+    /// What the controller was aiming for, if anything
+    pub goal: Option<f32>,
+    pub workers: usize,
+    pub target_workers: usize,
+    /// Milliseconds
+    pub p50: f64,
+    /// Milliseconds
+    pub p99: f64,
+    /// Proportional, integral, and derivative terms of the controller's output
+    pub pid: Option<(f32, f32, f32)>,
+}
+
+/// # RunReport
+///
+/// Everything a run measured, in a form that can be kept and compared: totals, latency
+/// percentiles, failures by category, and a timeline of rate, workers, and controller terms.
+///
+/// Reports are built up during a run with a `ReportBuilder`. `to_csv` gives the timeline as
+/// CSV and `totals_csv` the totals; with the `json` feature the whole report goes to and from
+/// JSON.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+pub struct RunReport {
+    /// Seconds the run took
+    pub elapsed: f64,
+    pub results: u64,
+    pub failures: u64,
+    /// Results per second
+    pub throughput: f32,
+    /// Fraction of results that failed, from 0 to 1
+    pub error_rate: f32,
+    pub latency: LatencySummary,
+    /// Failures per category
+    pub errors: BTreeMap<String, u64>,
+    pub timeline: Vec<ReportTick>,
+}
+
+const TIMELINE_HEADER: &str =
+    "at,results,failures,rate,goal,workers,target_workers,p50_ms,p99_ms,p,i,d";
+
+impl RunReport {
+    /// The timeline, one row per tick
+    pub fn to_csv(&self) -> String {
+        let mut csv = format!("{}\n", TIMELINE_HEADER);
+        let optional = |value: Option<f32>| value.map_or(String::new(), |v| v.to_string());
+
+        for tick in self.timeline.iter() {
+            let (p, i, d) = match tick.pid {
+                Some((p, i, d)) => (p.to_string(), i.to_string(), d.to_string()),
+                None => Default::default(),
+            };
+            writeln!(
+                csv,
+                "{:.3},{},{},{},{},{},{},{:.3},{:.3},{},{},{}",
+                tick.at,
+                tick.results,
+                tick.failures,
+                tick.rate,
+                optional(tick.goal),
+                tick.workers,
+                tick.target_workers,
+                tick.p50,
+                tick.p99,
+                p,
+                i,
+                d
+            )
+            .expect("writing to a string");
+        }
+
+        csv
+    }
+
+    /// The totals as a single CSV row, with a column per failure category
+    pub fn totals_csv(&self) -> String {
+        let mut header = "elapsed,results,failures,throughput,error_rate,\
+                          mean_ms,p50_ms,p90_ms,p99_ms,p999_ms,max_ms"
+            .to_string();
+        let latency = &self.latency;
+        let mut row = format!(
+            "{:.3},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+            self.elapsed,
+            self.results,
+            self.failures,
+            self.throughput,
+            self.error_rate,
+            latency.mean,
+            latency.p50,
+            latency.p90,
+            latency.p99,
+            latency.p999,
+            latency.max
+        );
+
+        for (category, count) in self.errors.iter() {
+            write!(header, ",errors.{}", category.replace(',', " ")).expect("writing to a string");
+            write!(row, ",{}", count).expect("writing to a string");
+        }
+
+        format!("{}\n{}\n", header, row)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("reports are always serializable")
+    }
+
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str(json).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+/// # ReportBuilder
+///
+/// Collects a `RunReport` while a run is going: `record` every result, and `tick` whenever the
+/// controller updates to add a row to the timeline. Call `stop` when the run's time is up and
+/// it's only waiting on results still in flight, so the wait doesn't count.
+///
+/// ```
+/// use clobber::{PidController, ReportBuilder, WorkerPool};
+/// use std::time::Duration;
+///
+/// let mut builder = ReportBuilder::new();
+/// let pid = PidController::new((0.001, 0.0, 0.0));
+///
+/// builder.record(&Duration::from_millis(12));
+/// builder.record_failure(&Duration::from_millis(40), "timeout");
+/// # let (send, _recv) = async_std::sync::channel::<Duration>(1);
+/// # async fn job(_: clobber::Job<(), Duration>) -> clobber::JobStatus { clobber::JobStatus::Done }
+/// # let pool = WorkerPool::new(job, send, 1);
+/// builder.tick(&pool.metrics(), Some(100.0), Some(&pid));
+///
+/// let report = builder.finish();
+/// assert_eq!(report.results, 2);
+/// assert_eq!(report.errors["timeout"], 1);
+/// println!("{}", report.to_csv());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReportBuilder {
+    start: Instant,
+    /// When the run's time was up, if it's been stopped
+    stopped: Option<Instant>,
+    latency: Histogram,
+    failures: u64,
+    errors: BTreeMap<String, u64>,
+    timeline: Vec<ReportTick>,
+    tick_start: Instant,
+    tick_latency: Histogram,
+    tick_failures: u64,
+}
+
+impl ReportBuilder {
+    /// Starts the clock
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            stopped: None,
+            latency: Histogram::new(),
+            failures: 0,
+            errors: BTreeMap::new(),
+            timeline: vec![],
+            tick_start: now,
+            tick_latency: Histogram::new(),
+            tick_failures: 0,
+        }
+    }
+
+    /// Records a result. Failures, going by `Sample::failed`, are counted as `failed`.
+    pub fn record(&mut self, sample: &impl Sample) {
+        match sample.failed() {
+            true => self.record_failure(sample, "failed"),
+            false => self.tick_latency.record(sample.latency()),
+        }
+    }
+
+    /// Records a result that failed, under `category`
+    pub fn record_failure(&mut self, sample: &impl Sample, category: &str) {
+        self.tick_latency.record(sample.latency());
+        self.tick_failures += 1;
+        match self.errors.get_mut(category) {
+            Some(count) => *count += 1,
+            None => {
+                self.errors.insert(category.to_string(), 1);
+            }
+        }
+    }
+
+    /// Closes out a row of the timeline, with the pool's worker counts and, if there's a
+    /// controller, its goal and terms
+    pub fn tick(&mut self, metrics: &PoolMetrics, goal: Option<f32>, pid: Option<&PidController>) {
+        let now = Instant::now();
+        let latency = self.tick_latency.take();
+        let secs = now.duration_since(self.tick_start).as_secs_f32();
+
+        self.timeline.push(ReportTick {
+            at: now.duration_since(self.start).as_secs_f64(),
+            results: latency.count(),
+            failures: self.tick_failures,
+            rate: match secs {
+                secs if secs > 0.0 => latency.count() as f32 / secs,
+                _ => 0.0,
+            },
+            goal,
+            workers: metrics.cur_workers,
+            target_workers: metrics.target_workers,
+            p50: ms(latency.percentile(50.0)),
+            p99: ms(latency.percentile(99.0)),
+            pid: pid.map(PidController::terms),
+        });
+
+        self.latency.merge(&latency);
+        self.failures += self.tick_failures;
+        self.tick_failures = 0;
+        self.tick_start = now;
+    }
+
+    /// The row `tick` added most recently
+    pub fn last_tick(&self) -> Option<&ReportTick> {
+        self.timeline.last()
+    }
+
+    /// Stops the clock. Results recorded afterwards still count, but the report's elapsed time
+    /// and throughput are as of now.
+    pub fn stop(&mut self) {
+        self.stopped.get_or_insert_with(Instant::now);
+    }
+
+    /// The report so far, counting anything recorded since the last tick in the totals
+    pub fn finish(mut self) -> RunReport {
+        self.latency.merge(&self.tick_latency);
+        self.failures += self.tick_failures;
+
+        let end = self.stopped.unwrap_or_else(Instant::now);
+        let elapsed = end.duration_since(self.start).as_secs_f64();
+        let results = self.latency.count();
+
+        RunReport {
+            elapsed,
+            results,
+            failures: self.failures,
+            throughput: match elapsed {
+                secs if secs > 0.0 => (results as f64 / secs) as f32,
+                _ => 0.0,
+            },
+            error_rate: match results {
+                0 => 0.0,
+                results => self.failures as f32 / results as f32,
+            },
+            latency: LatencySummary::from(&self.latency),
+            errors: self.errors,
+            timeline: self.timeline,
+        }
+    }
+}
+
+impl Default for ReportBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricsHandle;
+
+    fn report() -> RunReport {
+        let mut builder = ReportBuilder::new();
+        let metrics = MetricsHandle::new().snapshot();
+        let mut pid = PidController::new((0.5, 0.0, 0.0));
+        pid.update(10.0, 6.0);
+
+        for ms in 1..=100 {
+            builder.record(&Duration::from_millis(ms));
+        }
+        builder.tick(&metrics, Some(10.0), Some(&pid));
+        builder.record_failure(&Duration::from_millis(5), "status, 5xx");
+        builder.record_failure(&Duration::from_millis(5), "timeout");
+        builder.tick(&metrics, None, None);
+        builder.record(&Duration::from_millis(1));
+
+        builder.finish()
+    }
+
+    #[test]
+    fn totals_and_csv() {
+        let report = report();
+        assert_eq!(report.results, 103);
+        assert_eq!(report.failures, 2);
+        assert_eq!(report.timeline.len(), 2);
+        assert_eq!(report.timeline[0].pid, Some((2.0, 0.0, 0.0)));
+        assert!((report.latency.p50 - 50.0).abs() < 1.0, "{:?}", report.latency);
+
+        let csv = report.to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], TIMELINE_HEADER);
+        assert!(rows[1].ends_with(",2,0,0"), "{}", rows[1]);
+        assert!(rows[2].contains(",2,2,") && rows[2].ends_with(",,,"), "{}", rows[2]);
+
+        let totals = report.totals_csv();
+        let header = totals.lines().next().unwrap();
+        assert!(header.ends_with(",errors.status  5xx,errors.timeout"), "{}", header);
+        assert!(totals.lines().nth(1).unwrap().ends_with(",1,1"));
+    }
+
+    #[test]
+    fn stopped_clock() {
+        let mut builder = ReportBuilder::new();
+        for ms in 1..=100 {
+            builder.record(&Duration::from_millis(ms));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        builder.stop();
+
+        // a long wait for the last results doesn't water down the throughput
+        std::thread::sleep(Duration::from_millis(300));
+        builder.record(&Duration::from_millis(300));
+        let report = builder.finish();
+
+        assert_eq!(report.results, 101);
+        assert!(report.elapsed < 0.2, "{}", report.elapsed);
+        assert!(report.throughput > 101.0 / 0.2, "{}", report.throughput);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_round_trip() {
+        let report = report();
+        let read = RunReport::from_json(&report.to_json()).unwrap();
+        assert_eq!((read.results, read.failures), (report.results, report.failures));
+        assert_eq!(read.errors, report.errors);
+        assert_eq!(read.timeline.len(), 2);
+        assert_eq!(read.timeline[0].pid, report.timeline[0].pid);
+        // serde_json reads floats to within an ulp or so
+        assert!((read.latency.p90 - report.latency.p90).abs() < 1e-9);
+        assert!(RunReport::from_json("{}").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use async_std::{future, sync::Receiver};
+use std::time::{Duration, Instant};
+
+use crate::histogram::Histogram;
+
+/// # Sample
+///
+/// A pool output that measured something. The analysis tools in this crate (capacity search,
+/// scenarios, reports) work on any output type that can say how long it took.
+///
+/// `Duration` is a sample of itself, for workers that only send back timings.
+pub trait Sample {
+    /// How long the measured operation took
+    fn latency(&self) -> Duration;
+
+    /// Whether the operation failed. Failed samples still count as results.
+    fn failed(&self) -> bool {
+        false
+    }
+}
+
+impl Sample for Duration {
+    fn latency(&self) -> Duration {
+        *self
+    }
+}
+
+/// What `collect` read
+pub(crate) struct Collected {
+    pub results: usize,
+    pub failures: usize,
+    pub latency: Histogram,
+    /// Whether the channel is still open
+    pub open: bool,
+}
+
+/// Reads results for `duration`, or until the channel closes
+pub(crate) async fn collect<Out: Sample>(results: &Receiver<Out>, duration: Duration) -> Collected {
+    let deadline = Instant::now() + duration;
+    let mut collected =
+        Collected { results: 0, failures: 0, latency: Histogram::new(), open: true };
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return collected;
+        }
+
+        match future::timeout(remaining, results.recv()).await {
+            Ok(Ok(out)) => {
+                collected.results += 1;
+                if out.failed() {
+                    collected.failures += 1;
+                }
+                collected.latency.record(out.latency());
+            }
+            Ok(Err(_)) => {
+                collected.open = false;
+                return collected;
+            }
+            Err(_) => return collected,
+        }
+    }
+}
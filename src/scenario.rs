@@ -0,0 +1,556 @@
+use async_std::sync::Receiver;
+use crossbeam_channel::Sender;
+use log::debug;
+use std::time::{Duration, Instant};
+
+use crate::{
+    histogram::Histogram,
+    limiter::RateLimiter,
+    pid::PidController,
+    pool::WorkerPoolCommand,
+    sample::{collect, Sample},
+    setpoint::Setpoint,
+    slo::LatencyController,
+};
+
+/// How a stage decides the number of workers
+#[derive(Debug, Clone, PartialEq)]
+pub enum Control {
+    /// Runs a fixed number of workers
+    Workers(usize),
+    /// Caps the pool's rate limiter at `rate` per second, running `workers` workers to fill it.
+    /// Whatever limit the pool had before comes back when the stage ends; see
+    /// `Scenario::with_rate_limiter`.
+    RateLimit { rate: f32, workers: usize },
+    /// Drives the worker count with a `PidController` to hit a request rate
+    Rate { goal: Setpoint, gain: (f32, f32, f32) },
+    /// Drives the worker count with a `LatencyController` to hold a latency percentile
+    Latency { percentile: f64, target: Duration, gain: (f32, f32, f32) },
+}
+
+/// One step of a `Scenario`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    pub name: String,
+    pub duration: Duration,
+    pub control: Control,
+    /// Warmup stages run like any other, but are left out of the measured totals
+    pub warmup: bool,
+}
+
+impl Stage {
+    pub fn new(name: &str, duration: Duration, control: Control) -> Self {
+        Self { name: name.to_string(), duration, control, warmup: false }
+    }
+
+    /// A stage whose results don't count
+    pub fn warmup(name: &str, duration: Duration, control: Control) -> Self {
+        Self { warmup: true, ..Self::new(name, duration, control) }
+    }
+}
+
+/// What happened during one stage
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageSummary {
+    pub name: String,
+    /// Whether this stage counts towards the measured totals
+    pub measured: bool,
+    pub elapsed: Duration,
+    pub results: u64,
+    /// Results that were failures, going by `Sample::failed`
+    pub failures: u64,
+    pub latency: Histogram,
+    /// Fewest and most workers the stage asked for
+    pub workers: (usize, usize),
+}
+
+impl StageSummary {
+    fn new(name: &str, measured: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            measured,
+            elapsed: Duration::from_secs(0),
+            results: 0,
+            failures: 0,
+            latency: Histogram::new(),
+            workers: (usize::MAX, 0),
+        }
+    }
+
+    /// Results per second
+    pub fn throughput(&self) -> f32 {
+        match self.elapsed.as_secs_f32() {
+            secs if secs > 0.0 => self.results as f32 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Fraction of results that were failures, from 0 to 1
+    pub fn error_rate(&self) -> f32 {
+        match self.results {
+            0 => 0.0,
+            results => self.failures as f32 / results as f32,
+        }
+    }
+
+    fn record_workers(&mut self, workers: usize) {
+        self.workers = (self.workers.0.min(workers), self.workers.1.max(workers));
+    }
+}
+
+/// Per-stage summaries of a scenario run, in the order the stages ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioReport {
+    pub stages: Vec<StageSummary>,
+    /// Whatever the pool sent while draining
+    pub cooldown: StageSummary,
+}
+
+impl ScenarioReport {
+    /// Every measured stage added together
+    pub fn measured(&self) -> StageSummary {
+        let mut total = StageSummary::new("measured", true);
+
+        for stage in self.stages.iter().filter(|s| s.measured) {
+            total.elapsed += stage.elapsed;
+            total.results += stage.results;
+            total.failures += stage.failures;
+            total.latency.merge(&stage.latency);
+            total.record_workers(stage.workers.0);
+            total.record_workers(stage.workers.1);
+        }
+
+        total
+    }
+}
+
+/// # Scenario
+///
+/// A load test as a sequence of stages.
+///
+/// Each stage runs for a fixed time under its own `Control`: a fixed number of workers, a rate
+/// limit, or a controller chasing a rate or latency goal. Worker counts carry over from one
+/// stage to the next, so a controller picks up where the last stage left off. After the last
+/// stage the pool is drained with `WorkerPoolCommand::Drain`, and `work` returns once the
+/// running jobs have stopped.
+///
+/// Like `CapacitySearch`, the scenario drives a pool from the outside, so it needs the pool's
+/// command channel and the receiving end of its output channel:
+/// ```no_run
+/// # use async_std::{sync::channel, task};
+/// # use clobber::{Control, Job, JobStatus, Scenario, Setpoint, Stage, WorkerPool};
+/// # use std::time::Duration;
+/// # async fn request(job: Job<(), Duration>) -> JobStatus { JobStatus::Done }
+/// # task::block_on(async {
+/// let (send, recv) = channel(1024);
+/// let mut pool = WorkerPool::new(request, send, 1);
+/// for _ in 0..1000 {
+///     pool.push(());
+/// }
+///
+/// let minute = Duration::from_secs(60);
+/// let scenario = Scenario::new()
+///     .with_stage(Stage::warmup("warmup", minute, Control::Workers(10)))
+///     .with_stage(Stage::new(
+///         "ramp",
+///         5 * minute,
+///         Control::Rate {
+///             goal: Setpoint::Ramp { from: 100.0, to: 2000.0, over: 5 * minute },
+///             gain: (0.001, 0.0, 0.0),
+///         },
+///     ));
+///
+/// let run = task::spawn(scenario.run(pool.command_channel(), recv));
+/// pool.work().await;
+/// drop(pool); // lets the scenario see that draining is over
+///
+/// for stage in run.await.stages {
+///     println!("{}: {:.1}/s, {}", stage.name, stage.throughput(), stage.latency.percentiles());
+/// }
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    stages: Vec<Stage>,
+    tick: Duration,
+    cooldown: Duration,
+    max_workers: usize,
+    max_error_rate: Option<f32>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self {
+            stages: vec![],
+            tick: Duration::from_millis(100),
+            cooldown: Duration::from_secs(30),
+            max_workers: 1024,
+            max_error_rate: None,
+            rate_limiter: None,
+        }
+    }
+
+    pub fn with_stage(mut self, stage: Stage) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// How often controllers are updated
+    pub fn with_tick(mut self, tick: Duration) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    /// Longest to wait for the pool to drain before stopping it outright
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Most workers any controller is allowed to ask for
+    pub fn with_max_workers(mut self, max_workers: usize) -> Self {
+        self.max_workers = max_workers.max(1);
+        self
+    }
+
+    /// Feeds the error rate into the controllers: in any tick where more than `rate` (0 to 1)
+    /// of the results fail, `Rate` and `Latency` stages cut their workers by the excess
+    /// instead of doing what their controller says. Fixed stages are left alone.
+    pub fn with_max_error_rate(mut self, rate: f32) -> Self {
+        self.max_error_rate = Some(rate);
+        self
+    }
+
+    /// The pool's `rate_limiter`, so a `RateLimit` stage knows what limit to put back when it
+    /// ends. Without it, the pool is left unlimited after such a stage. Other stages never touch
+    /// the pool's limit.
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Runs every stage against a pool, then drains it
+    pub async fn run<Out: Sample>(
+        self,
+        commands: Sender<WorkerPoolCommand>,
+        results: Receiver<Out>,
+    ) -> ScenarioReport {
+        let mut summaries = vec![];
+        let mut workers = 1;
+        let mut open = true;
+        // the pool's own limit, while a stage has replaced it with one of its own
+        let mut replaced_limit = None;
+
+        for stage in self.stages.iter() {
+            if !open {
+                break;
+            }
+
+            let mut summary = StageSummary::new(&stage.name, !stage.warmup);
+            let mut control = StageControl::new(&stage.control, workers, self.max_workers);
+            let start = Instant::now();
+
+            match control.rate_limit() {
+                Some(rate) => {
+                    if replaced_limit.is_none() {
+                        replaced_limit = Some(self.rate_limiter.as_ref().and_then(|l| l.rate()));
+                    }
+                    commands.send(WorkerPoolCommand::SetRateLimit(Some(rate))).ok();
+                }
+                None => {
+                    if let Some(limit) = replaced_limit.take() {
+                        commands.send(WorkerPoolCommand::SetRateLimit(limit)).ok();
+                    }
+                }
+            }
+
+            while open && start.elapsed() < stage.duration {
+                if workers != control.workers() {
+                    workers = control.workers();
+                    commands.send(WorkerPoolCommand::SetWorkerCount(workers)).ok();
+                }
+                summary.record_workers(workers);
+
+                let tick = self.tick.min(remaining(start, stage.duration));
+                let tick_start = Instant::now();
+                let collected = collect(&results, tick).await;
+                open = collected.open;
+
+                let rate = collected.results as f32 / tick_start.elapsed().as_secs_f32();
+                let error_rate = match collected.results {
+                    0 => 0.0,
+                    n => collected.failures as f32 / n as f32,
+                };
+                let excess = match self.max_error_rate {
+                    Some(max) if error_rate > max => Some(error_rate - max),
+                    _ => None,
+                };
+
+                control.tick(start.elapsed(), rate, &collected.latency, excess);
+                summary.results += collected.results as u64;
+                summary.failures += collected.failures as u64;
+                summary.latency.merge(&collected.latency);
+            }
+
+            summary.elapsed = start.elapsed();
+            debug!(
+                "Scenario, {}, {}, {}",
+                summary.name,
+                summary.throughput(),
+                summary.latency.percentiles()
+            );
+            summaries.push(summary);
+        }
+
+        if let Some(limit) = replaced_limit.take() {
+            commands.send(WorkerPoolCommand::SetRateLimit(limit)).ok();
+        }
+
+        let mut cooldown = StageSummary::new("cooldown", false);
+        let start = Instant::now();
+        commands.send(WorkerPoolCommand::Drain).ok();
+
+        while open && start.elapsed() < self.cooldown {
+            let collected = collect(&results, remaining(start, self.cooldown)).await;
+            open = collected.open;
+            cooldown.results += collected.results as u64;
+            cooldown.failures += collected.failures as u64;
+            cooldown.latency.merge(&collected.latency);
+        }
+
+        // didn't drain in time
+        if open {
+            commands.send(WorkerPoolCommand::Stop).ok();
+        }
+
+        cooldown.elapsed = start.elapsed();
+        cooldown.record_workers(0);
+
+        ScenarioReport { stages: summaries, cooldown }
+    }
+}
+
+fn remaining(start: Instant, duration: Duration) -> Duration {
+    duration.checked_sub(start.elapsed()).unwrap_or_default()
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The running state of a stage's `Control`
+enum StageControl<'a> {
+    Fixed { workers: usize, rate_limit: Option<f32> },
+    Rate { goal: &'a Setpoint, pid: PidController, workers: f32, max_workers: usize },
+    Latency(LatencyController),
+}
+
+impl<'a> StageControl<'a> {
+    fn new(control: &'a Control, workers: usize, max_workers: usize) -> Self {
+        match control {
+            Control::Workers(n) => StageControl::Fixed { workers: *n, rate_limit: None },
+            Control::RateLimit { rate, workers } => {
+                StageControl::Fixed { workers: *workers, rate_limit: Some(*rate) }
+            }
+            Control::Rate { goal, gain } => StageControl::Rate {
+                goal,
+                pid: PidController::new(*gain),
+                workers: workers as f32,
+                max_workers,
+            },
+            Control::Latency { percentile, target, gain } => StageControl::Latency(
+                LatencyController::new(*percentile, *target, *gain)
+                    .with_worker_limits(1, max_workers)
+                    .with_initial_workers(workers),
+            ),
+        }
+    }
+
+    fn rate_limit(&self) -> Option<f32> {
+        match self {
+            StageControl::Fixed { rate_limit, .. } => *rate_limit,
+            _ => None,
+        }
+    }
+
+    fn workers(&self) -> usize {
+        match self {
+            StageControl::Fixed { workers, .. } => (*workers).max(1),
+            StageControl::Rate { workers, .. } => workers.floor() as usize,
+            StageControl::Latency(controller) => controller.workers(),
+        }
+    }
+
+    /// `excess` is how far the error rate is over the scenario's limit, if it is
+    fn tick(&mut self, elapsed: Duration, rate: f32, latency: &Histogram, excess: Option<f32>) {
+        let back_off = |workers: f32| (workers * (1.0 - excess.unwrap_or(0.0))).max(1.0);
+
+        match self {
+            StageControl::Fixed { .. } => {}
+            StageControl::Rate { goal, pid, workers, max_workers } => {
+                *workers = match excess {
+                    Some(_) => back_off(*workers),
+                    None => {
+                        pid.update(goal.at(elapsed), rate);
+                        (*workers + pid.output()).max(1.0).min(*max_workers as f32)
+                    }
+                };
+            }
+            StageControl::Latency(controller) => {
+                controller.record_histogram(latency);
+                controller.tick();
+                if excess.is_some() {
+                    let workers = back_off(controller.workers() as f32);
+                    controller.set_workers(workers as usize);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Job, JobStatus, WorkerPool};
+    use async_std::{sync::channel, task};
+    use futures_await_test::async_test;
+
+    async fn request(job: Job<(), Duration>) -> JobStatus {
+        loop {
+            if job.stop_requested() {
+                return JobStatus::Stopped;
+            }
+
+            job.limiter.acquire().await;
+            task::sleep(Duration::from_millis(10)).await;
+            job.results.send(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[async_test]
+    async fn stages_and_cooldown() {
+        let (send, recv) = channel(1024);
+        let mut pool = WorkerPool::new(request, send, 1);
+        for _ in 0..8 {
+            pool.push(());
+        }
+
+        let stage = Duration::from_millis(300);
+        let scenario = Scenario::new()
+            .with_tick(Duration::from_millis(50))
+            .with_cooldown(Duration::from_secs(5))
+            .with_stage(Stage::warmup("warmup", stage, Control::Workers(1)))
+            .with_stage(Stage::new("two", stage, Control::Workers(2)))
+            .with_stage(Stage::new(
+                "limited",
+                stage,
+                Control::RateLimit { rate: 20.0, workers: 4 },
+            ));
+
+        let run = task::spawn(scenario.run(pool.command_channel(), recv));
+        pool.work().await;
+        drop(pool);
+        let report = run.await;
+
+        let names: Vec<_> = report.stages.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["warmup", "two", "limited"]);
+        assert_eq!(report.stages[1].workers, (2, 2));
+
+        // two workers at 10ms each is about 200/s, double what one manages in warmup
+        let (warmup, two) = (report.stages[0].throughput(), report.stages[1].throughput());
+        assert!(two > warmup * 1.5, "{} vs {}", two, warmup);
+
+        // four workers, but held to 20/s
+        assert!(report.stages[2].throughput() < 40.0, "{}", report.stages[2].throughput());
+
+        let measured = report.measured();
+        assert_eq!(measured.results, report.stages[1].results + report.stages[2].results);
+        assert_eq!(measured.workers, (2, 4));
+        assert!(report.cooldown.elapsed < Duration::from_secs(5));
+    }
+
+    #[async_test]
+    async fn keeps_the_pools_rate_limit() {
+        let (send, recv) = channel(1024);
+        let mut pool = WorkerPool::new(request, send, 1);
+        pool.set_rate_limit(Some(50.0), 1);
+        let limiter = pool.rate_limiter();
+        for _ in 0..8 {
+            pool.push(());
+        }
+
+        let stage = Duration::from_millis(300);
+        let scenario = Scenario::new()
+            .with_tick(Duration::from_millis(50))
+            .with_rate_limiter(pool.rate_limiter())
+            .with_stage(Stage::new("before", stage, Control::Workers(4)))
+            .with_stage(Stage::new("limited", stage, Control::RateLimit { rate: 10.0, workers: 4 }))
+            .with_stage(Stage::new("after", stage, Control::Workers(4)));
+
+        let run = task::spawn(scenario.run(pool.command_channel(), recv));
+        pool.work().await;
+        drop(pool);
+        let report = run.await;
+
+        // four workers could manage 400/s, but the pool's limit holds outside the limited stage
+        let throughputs: Vec<f32> = report.stages.iter().map(|s| s.throughput()).collect();
+        assert!(throughputs[0] < 75.0 && throughputs[2] < 75.0, "{:?}", throughputs);
+        assert!(throughputs[1] < 25.0, "{:?}", throughputs);
+        assert!(throughputs[2] > throughputs[1] * 2.0, "{:?}", throughputs);
+        assert_eq!(limiter.rate(), Some(50.0));
+    }
+
+    /// A result that's always a failure
+    #[derive(Debug, Copy, Clone)]
+    struct Failure;
+
+    impl Sample for Failure {
+        fn latency(&self) -> Duration {
+            Duration::from_millis(10)
+        }
+
+        fn failed(&self) -> bool {
+            true
+        }
+    }
+
+    async fn failing(job: Job<(), Failure>) -> JobStatus {
+        loop {
+            if job.stop_requested() {
+                return JobStatus::Stopped;
+            }
+
+            task::sleep(Duration::from_millis(10)).await;
+            job.results.send(Failure).await;
+        }
+    }
+
+    #[async_test]
+    async fn backs_off_on_errors() {
+        let (send, recv) = channel(1024);
+        let mut pool = WorkerPool::new(failing, send, 1);
+        for _ in 0..64 {
+            pool.push(());
+        }
+
+        // a goal it can't reach would have the controller adding workers every tick
+        let goal = Control::Rate { goal: Setpoint::Constant(1e6), gain: (0.01, 0.0, 0.0) };
+        let scenario = Scenario::new()
+            .with_tick(Duration::from_millis(50))
+            .with_max_error_rate(0.1)
+            .with_stage(Stage::new("failing", Duration::from_millis(300), goal));
+
+        let run = task::spawn(scenario.run(pool.command_channel(), recv));
+        pool.work().await;
+        drop(pool);
+        let report = run.await;
+
+        let stage = &report.stages[0];
+        assert_eq!(stage.workers, (1, 1));
+        assert!(stage.results > 0);
+        assert_eq!(stage.error_rate(), 1.0);
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    f32::consts::PI,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    time::Duration,
+};
+
+/// # Setpoint
+///
+/// A goal that changes over the course of a run.
+///
+/// Instead of a fixed goal, the control loop asks the setpoint what the goal is at the current
+/// elapsed time and feeds that into `PidController::update`. That's enough for soak tests
+/// (`Constant`), capacity ramps, spike tests, and diurnal traffic (`Sine`), without writing a
+/// custom loop for each. The goal is in whatever units the loop controls: requests per second,
+/// milliseconds of latency, and so on.
+///
+/// ```
+/// use clobber::{PidController, Setpoint};
+/// use std::time::Duration;
+///
+/// // 100 rps, climbing by 100 every 30 seconds up to 500
+/// let setpoint = Setpoint::Staircase {
+///     start: 100.0,
+///     step: 100.0,
+///     every: Duration::from_secs(30),
+///     steps: 4,
+/// };
+/// assert_eq!(setpoint.at(Duration::from_secs(65)), 300.0);
+///
+/// let mut pid = PidController::new((0.01, 0.0, 0.0));
+/// let (elapsed, measured_rps) = (Duration::from_secs(10), 80.0);
+/// pid.update(setpoint.at(elapsed), measured_rps);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Setpoint {
+    /// The same goal for the whole run
+    Constant(f32),
+    /// Moves linearly from `from` to `to` over `over`, then holds at `to`
+    Ramp { from: f32, to: f32, over: Duration },
+    /// Starts at `start` and adds `step` every `every`, `steps` times, then holds
+    Staircase { start: f32, step: f32, every: Duration, steps: usize },
+    /// Swings `amplitude` either side of `mean`, once per `period`
+    Sine { mean: f32, amplitude: f32, period: Duration },
+    /// Climbs linearly from `low` to `high` each `period`, then drops straight back
+    Sawtooth { low: f32, high: f32, period: Duration },
+    /// Holds at `base`, jumps to `peak` at `at` for `width`, then goes back to `base`. See
+    /// `Setpoint::spike` for building one from untrusted durations.
+    Spike { base: f32, peak: f32, at: Duration, width: Duration },
+    /// Interpolates linearly between `(elapsed, goal)` points, sorted by time. Holds the first
+    /// goal before the first point and the last goal after the last.
+    Piecewise(Vec<(Duration, f32)>),
+}
+
+impl Setpoint {
+    /// The goal `elapsed` into the run
+    pub fn at(&self, elapsed: Duration) -> f32 {
+        match self {
+            Setpoint::Constant(goal) => *goal,
+            Setpoint::Ramp { from, to, over } => from + (to - from) * fraction(elapsed, *over),
+            Setpoint::Staircase { start, step, every, steps } => {
+                let taken = match every.as_secs_f64() {
+                    e if e > 0.0 => (elapsed.as_secs_f64() / e) as usize,
+                    _ => *steps,
+                };
+                start + step * taken.min(*steps) as f32
+            }
+            Setpoint::Sine { mean, amplitude, period } => {
+                mean + amplitude * (2.0 * PI * cycle(elapsed, *period)).sin()
+            }
+            Setpoint::Sawtooth { low, high, period } => {
+                low + (high - low) * cycle(elapsed, *period)
+            }
+            Setpoint::Spike { base, peak, at, width } => {
+                // a spike that would end past the largest `Duration` never ends
+                let before_end = match at.checked_add(*width) {
+                    Some(end) => elapsed < end,
+                    None => true,
+                };
+                match elapsed >= *at && before_end {
+                    true => *peak,
+                    false => *base,
+                }
+            }
+            Setpoint::Piecewise(points) => piecewise(points, elapsed),
+        }
+    }
+
+    /// A `Spike`, rejected if it would end too far out for a `Duration` to hold
+    pub fn spike(base: f32, peak: f32, at: Duration, width: Duration) -> io::Result<Self> {
+        match at.checked_add(width) {
+            Some(_) => Ok(Setpoint::Spike { base, peak, at, width }),
+            None => Err(io::Error::new(ErrorKind::InvalidInput, "spike ends too late")),
+        }
+    }
+
+    /// Reads a piecewise-linear schedule from a file. See `parse`.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses a piecewise-linear schedule, one `seconds, goal` point per line:
+    /// ```text
+    /// # overnight lull, then the morning rush
+    /// 0, 200
+    /// 60, 200
+    /// 90, 1500.5
+    /// ```
+    /// Blank lines and lines starting with `#` are skipped. Points must be in time order.
+    pub fn parse(schedule: &str) -> io::Result<Self> {
+        let mut points: Vec<(Duration, f32)> = vec![];
+
+        for (number, line) in schedule.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |reason: &str| {
+                let message = format!("line {}: {}: {:?}", number + 1, reason, line);
+                io::Error::new(ErrorKind::InvalidData, message)
+            };
+
+            let mut fields = line.split(',').map(str::trim);
+            let (seconds, goal) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(seconds), Some(goal), None) => (seconds, goal),
+                _ => return Err(invalid("expected `seconds, goal`")),
+            };
+
+            let seconds: f64 = seconds.parse().map_err(|_| invalid("bad time"))?;
+            let goal: f32 = goal.parse().map_err(|_| invalid("bad goal"))?;
+            if !seconds.is_finite() || seconds < 0.0 {
+                return Err(invalid("bad time"));
+            }
+
+            let time = Duration::from_secs_f64(seconds);
+            if let Some(&(last, _)) = points.last() {
+                if time < last {
+                    return Err(invalid("out of order"));
+                }
+            }
+
+            points.push((time, goal));
+        }
+
+        if points.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "schedule has no points"));
+        }
+
+        Ok(Setpoint::Piecewise(points))
+    }
+}
+
+/// How far through `over` we are, from 0 to 1
+fn fraction(elapsed: Duration, over: Duration) -> f32 {
+    match over.as_secs_f64() {
+        over if over > 0.0 => (elapsed.as_secs_f64() / over).min(1.0) as f32,
+        _ => 1.0,
+    }
+}
+
+/// How far through the current `period` we are, from 0 to 1
+fn cycle(elapsed: Duration, period: Duration) -> f32 {
+    match period.as_secs_f64() {
+        period if period > 0.0 => (elapsed.as_secs_f64() % period / period) as f32,
+        _ => 0.0,
+    }
+}
+
+fn piecewise(points: &[(Duration, f32)], elapsed: Duration) -> f32 {
+    let after = points.iter().position(|&(time, _)| time > elapsed);
+
+    match after {
+        None => points.last().map_or(0.0, |&(_, goal)| goal),
+        Some(0) => points[0].1,
+        Some(index) => {
+            let (start, from) = points[index - 1];
+            let (end, to) = points[index];
+            from + (to - from) * fraction(elapsed - start, end - start)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(n: f32) -> Duration {
+        Duration::from_secs_f32(n)
+    }
+
+    fn close_to(actual: f32, expected: f32) -> bool {
+        (actual - expected).abs() < 0.01
+    }
+
+    #[test]
+    fn shapes() {
+        let ramp = Setpoint::Ramp { from: 100.0, to: 200.0, over: secs(10.0) };
+        assert_eq!(ramp.at(secs(0.0)), 100.0);
+        assert!(close_to(ramp.at(secs(2.5)), 125.0));
+        assert_eq!(ramp.at(secs(60.0)), 200.0);
+
+        let stairs = Setpoint::Staircase { start: 10.0, step: 5.0, every: secs(1.0), steps: 2 };
+        assert_eq!(stairs.at(secs(0.5)), 10.0);
+        assert_eq!(stairs.at(secs(1.5)), 15.0);
+        assert_eq!(stairs.at(secs(9.0)), 20.0);
+
+        let sine = Setpoint::Sine { mean: 100.0, amplitude: 50.0, period: secs(4.0) };
+        assert!(close_to(sine.at(secs(1.0)), 150.0));
+        assert!(close_to(sine.at(secs(3.0)), 50.0));
+        assert!(close_to(sine.at(secs(4.0)), 100.0));
+
+        let saw = Setpoint::Sawtooth { low: 0.0, high: 10.0, period: secs(2.0) };
+        assert!(close_to(saw.at(secs(1.0)), 5.0));
+        assert!(close_to(saw.at(secs(3.5)), 7.5));
+
+        let spike = Setpoint::Spike { base: 1.0, peak: 9.0, at: secs(5.0), width: secs(1.0) };
+        assert_eq!(spike.at(secs(4.9)), 1.0);
+        assert_eq!(spike.at(secs(5.0)), 9.0);
+        assert_eq!(spike.at(secs(6.0)), 1.0);
+
+        let forever = Duration::from_secs(u64::MAX);
+        assert!(Setpoint::spike(1.0, 9.0, forever, forever).is_err());
+        assert_eq!(Setpoint::spike(1.0, 9.0, secs(5.0), secs(1.0)).unwrap(), spike);
+        let endless = Setpoint::Spike { base: 1.0, peak: 9.0, at: secs(5.0), width: forever };
+        assert_eq!(endless.at(secs(1e6)), 9.0);
+    }
+
+    #[test]
+    fn parse_schedule() {
+        let schedule = Setpoint::parse("# warmup\n0, 100\n\n10, 300\n20,300\n30, 0\n").unwrap();
+
+        assert_eq!(schedule.at(secs(0.0)), 100.0);
+        assert!(close_to(schedule.at(secs(5.0)), 200.0));
+        assert_eq!(schedule.at(secs(15.0)), 300.0);
+        assert!(close_to(schedule.at(secs(25.0)), 150.0));
+        assert_eq!(schedule.at(secs(99.0)), 0.0);
+
+        assert!(Setpoint::parse("").is_err());
+        assert!(Setpoint::parse("0, 1\nfoo, 2").is_err());
+        assert!(Setpoint::parse("5, 1\n4, 2").is_err());
+        assert!(Setpoint::parse("1, 2, 3").is_err());
+    }
+}
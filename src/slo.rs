@@ -0,0 +1,192 @@
+use log::debug;
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{histogram::Histogram, pid::PidController};
+
+/// # LatencyController
+///
+/// Picks a worker count by holding a latency percentile at a target, rather than by chasing a
+/// request rate.
+///
+/// Job latencies are recorded as they come in, and every tick the controller looks at the
+/// chosen percentile over a sliding window of recent ticks. The gap between that and the
+/// target (in milliseconds) goes through a `PidController`, whose output nudges the worker
+/// count: under the target means there's headroom for more workers, over it means back off.
+///
+/// ```
+/// use clobber::LatencyController;
+/// use std::time::Duration;
+///
+/// // keep p99 under 200ms, looking at the last 20 ticks
+/// let target = Duration::from_millis(200);
+/// let mut controller = LatencyController::new(99.0, target, (0.01, 0.0, 0.0))
+///     .with_window(20)
+///     .with_worker_limits(1, 500);
+///
+/// controller.record(Duration::from_millis(35));
+/// let workers = controller.tick();
+/// // command.send(WorkerPoolCommand::SetWorkerCount(workers));
+/// # assert!(workers > 1);
+/// ```
+pub struct LatencyController {
+    percentile: f64,
+    target: Duration,
+    pid: PidController,
+    /// Completed ticks, oldest first
+    window: VecDeque<Histogram>,
+    window_len: usize,
+    /// Latencies recorded since the last tick
+    current: Histogram,
+    workers: f32,
+    min_workers: usize,
+    max_workers: usize,
+}
+
+impl LatencyController {
+    /// Holds latency at `percentile` (0 to 100) to `target`, with PID `gain` in workers per
+    /// millisecond of error.
+    pub fn new(percentile: f64, target: Duration, gain: (f32, f32, f32)) -> Self {
+        Self {
+            percentile,
+            target,
+            pid: PidController::new(gain),
+            window: VecDeque::new(),
+            window_len: 10,
+            current: Histogram::new(),
+            workers: 1.0,
+            min_workers: 1,
+            max_workers: usize::MAX,
+        }
+    }
+
+    /// How many ticks of latency to consider. Longer windows are steadier but slower to react.
+    pub fn with_window(mut self, ticks: usize) -> Self {
+        self.window_len = ticks.max(1);
+        self
+    }
+
+    /// Bounds on the worker count the controller will recommend
+    pub fn with_worker_limits(mut self, min: usize, max: usize) -> Self {
+        self.min_workers = min;
+        self.max_workers = max.max(min);
+        self.workers = self.clamp(self.workers);
+        self
+    }
+
+    /// Worker count to start from
+    pub fn with_initial_workers(mut self, workers: usize) -> Self {
+        self.workers = self.clamp(workers as f32);
+        self
+    }
+
+    /// Records the latency of one job
+    pub fn record(&mut self, latency: Duration) {
+        self.current.record(latency);
+    }
+
+    /// Records a batch of latencies at once
+    pub fn record_histogram(&mut self, latencies: &Histogram) {
+        self.current.merge(latencies);
+    }
+
+    /// Closes out the current tick and returns the recommended number of workers.
+    ///
+    /// If nothing has been recorded in the whole window there's nothing to go on, so the
+    /// recommendation stays where it was.
+    pub fn tick(&mut self) -> usize {
+        self.window.push_back(self.current.take());
+        while self.window.len() > self.window_len {
+            self.window.pop_front();
+        }
+
+        if let Some(observed) = self.observed() {
+            let goal = self.target.as_secs_f32() * 1000.0;
+            let current = observed.as_secs_f32() * 1000.0;
+
+            self.pid.update(goal, current);
+            self.workers = self.clamp(self.workers + self.pid.output());
+
+            debug!("LatencyController, {}, {}, {}", goal, current, self.workers);
+        }
+
+        self.workers()
+    }
+
+    /// The controlled percentile over the window, or `None` if the window is empty
+    pub fn observed(&self) -> Option<Duration> {
+        let mut merged = Histogram::new();
+        for histogram in self.window.iter() {
+            merged.merge(histogram);
+        }
+
+        if merged.is_empty() {
+            return None;
+        }
+
+        Some(merged.percentile(self.percentile))
+    }
+
+    /// The current recommendation
+    pub fn workers(&self) -> usize {
+        self.workers.floor() as usize
+    }
+
+    /// Overrides the recommendation, e.g. to back off for reasons the controller can't see.
+    /// The next tick carries on from here.
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = self.clamp(workers as f32);
+    }
+
+    pub fn target(&self) -> Duration {
+        self.target
+    }
+
+    /// Changes the latency target, e.g. from a setpoint schedule
+    pub fn set_target(&mut self, target: Duration) {
+        self.target = target;
+    }
+
+    /// The underlying PID controller, for inspecting its terms
+    pub fn pid(&self) -> &PidController {
+        &self.pid
+    }
+
+    fn clamp(&self, workers: f32) -> f32 {
+        workers.max(self.min_workers as f32).min(self.max_workers as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converges_on_target() {
+        let mut controller =
+            LatencyController::new(99.0, Duration::from_millis(100), (0.01, 0.0, 0.0))
+                .with_window(3)
+                .with_worker_limits(1, 50);
+
+        // a pretend target where every worker adds 10ms of latency
+        for _ in 0..200 {
+            let workers = controller.workers() as u64;
+            for _ in 0..100 {
+                controller.record(Duration::from_millis(workers * 10));
+            }
+            controller.tick();
+        }
+
+        let workers = controller.workers();
+        assert!((9..=10).contains(&workers), "{}", workers);
+    }
+
+    #[test]
+    fn holds_without_samples() {
+        let mut controller =
+            LatencyController::new(99.0, Duration::from_millis(100), (1.0, 0.0, 0.0))
+                .with_initial_workers(7);
+
+        assert_eq!(controller.tick(), 7);
+        assert_eq!(controller.observed(), None);
+    }
+}
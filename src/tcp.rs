@@ -0,0 +1,449 @@
+use async_std::{future, io::prelude::*, net::TcpStream};
+use log::warn;
+use std::{
+    borrow::Cow,
+    io::{self, ErrorKind},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    pool::{Job, JobStatus},
+    sample::Sample,
+    template::PayloadTemplate,
+};
+
+/// How to tell that a response is over
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadUntil {
+    /// Exactly this many bytes
+    Bytes(usize),
+    /// Up to and including this delimiter, e.g. `b"\n"`
+    Delimiter(Vec<u8>),
+    /// Everything until the server closes the connection. Connections can't be reused.
+    Close,
+}
+
+/// # TcpRequest
+///
+/// One request/response exchange for `tcp_worker` to repeat: connect to `addr`, write the
+/// payload, read back a response.
+///
+/// Requests are cheap to clone, so the usual setup is to push one per worker.
+///
+/// ```no_run
+/// # use async_std::{sync::channel, task};
+/// use clobber::{tcp_worker, ReadUntil, TcpRequest, WorkerPool};
+/// use std::time::Duration;
+///
+/// let request = TcpRequest::new("127.0.0.1:6379", b"PING\r\n".to_vec())
+///     .read_until(ReadUntil::Delimiter(b"\r\n".to_vec()))
+///     .with_reuse(true)
+///     .with_timeout(Duration::from_secs(1));
+///
+/// let (send, recv) = channel(1024);
+/// let mut pool = WorkerPool::new(tcp_worker, send, 8);
+/// for _ in 0..8 {
+///     pool.push(request.clone());
+/// }
+///
+/// task::spawn(async move {
+///     while let Ok(response) = recv.recv().await {
+///         if let Some(error) = response.error {
+///             println!("{:?}", error);
+///         }
+///     }
+/// });
+/// task::block_on(pool.work());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TcpRequest {
+    addr: String,
+    payload: Arc<Vec<u8>>,
+    template: Option<Arc<PayloadTemplate>>,
+    read: ReadUntil,
+    reuse: bool,
+    timeout: Option<Duration>,
+    count: Option<usize>,
+}
+
+impl TcpRequest {
+    /// Writes `payload` to `addr` and reads until the server closes the connection
+    pub fn new(addr: &str, payload: Vec<u8>) -> Self {
+        Self {
+            addr: addr.to_string(),
+            payload: Arc::new(payload),
+            template: None,
+            read: ReadUntil::Close,
+            reuse: false,
+            timeout: None,
+            count: None,
+        }
+    }
+
+    pub fn read_until(mut self, read: ReadUntil) -> Self {
+        self.read = read;
+        self
+    }
+
+    /// Keeps the connection open between requests instead of reconnecting every time. A
+    /// connection that errors is dropped and the next request reconnects.
+    pub fn with_reuse(mut self, reuse: bool) -> Self {
+        self.reuse = reuse;
+        self
+    }
+
+    /// Gives up on a request, including connecting, after `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Makes this many requests and finishes, instead of looping until stopped
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// Sends a payload rendered from `template` with every request, instead of the same bytes
+    pub fn with_template(mut self, template: PayloadTemplate) -> Self {
+        self.template = Some(Arc::new(template));
+        self
+    }
+
+    /// What to write for the next request
+    fn next_payload(&self) -> io::Result<Cow<'_, [u8]>> {
+        match self.template.as_ref() {
+            Some(template) => template.render().map(Cow::Owned),
+            None => Ok(Cow::Borrowed(&self.payload)),
+        }
+    }
+}
+
+/// Where a request went wrong
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TcpError {
+    Connect(ErrorKind),
+    Write(ErrorKind),
+    Read(ErrorKind),
+    /// The connection closed before the response was complete
+    Closed,
+    TimedOut,
+}
+
+/// The outcome of one request
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TcpResponse {
+    pub started: Instant,
+    /// Time spent connecting, if this request opened a new connection
+    pub connect: Option<Duration>,
+    /// Time from starting the request to finishing the response or failing
+    pub latency: Duration,
+    pub bytes_read: usize,
+    pub error: Option<TcpError>,
+}
+
+impl Sample for TcpResponse {
+    fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// # tcp_worker
+///
+/// A worker for `WorkerPool` that loops a `TcpRequest` against a server, sending a
+/// `TcpResponse` for every request.
+///
+/// It runs until stopped, or until it's made the request's `count` of requests. Jobs started
+/// by an open-loop pool make a single request, since the pool is deciding when requests
+/// happen. Each request waits on the pool's rate limiter first.
+///
+/// A job whose payload template can't be rendered fails without sending anything.
+pub async fn tcp_worker(job: Job<TcpRequest, TcpResponse>) -> JobStatus {
+    let request = &job.task;
+    let mut remaining = match job.scheduled {
+        Some(_) => Some(1),
+        None => request.count,
+    };
+    let mut connection: Option<Connection> = None;
+
+    loop {
+        if job.stop_requested() {
+            return JobStatus::Stopped;
+        }
+
+        match remaining {
+            Some(0) => return JobStatus::Done,
+            Some(n) => remaining = Some(n - 1),
+            None => {}
+        }
+
+        let payload = match request.next_payload() {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("couldn't render a payload for {}: {}", request.addr, e);
+                return JobStatus::Failed;
+            }
+        };
+
+        job.limiter.acquire().await;
+
+        let started = Instant::now();
+        let mut exchange = Exchange { started, connect: None, bytes_read: 0 };
+        let result = match request.timeout {
+            Some(timeout) => {
+                let attempt = exchange.run(request, &payload, &mut connection);
+                match future::timeout(timeout, attempt).await {
+                    Ok(result) => result,
+                    Err(_) => Err(TcpError::TimedOut),
+                }
+            }
+            None => exchange.run(request, &payload, &mut connection).await,
+        };
+
+        // a failed connection is in an unknown state, so start over
+        if result.is_err() || !request.reuse || request.read == ReadUntil::Close {
+            connection = None;
+        }
+
+        let response = TcpResponse {
+            started,
+            connect: exchange.connect,
+            latency: started.elapsed(),
+            bytes_read: exchange.bytes_read,
+            error: result.err(),
+        };
+        job.results.send(response).await;
+    }
+}
+
+/// An open connection, and whatever the server sent past the end of the last response
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+/// Progress through a single request, kept outside the future so a timeout doesn't lose it
+struct Exchange {
+    started: Instant,
+    connect: Option<Duration>,
+    bytes_read: usize,
+}
+
+impl Exchange {
+    async fn run(
+        &mut self,
+        request: &TcpRequest,
+        payload: &[u8],
+        connection: &mut Option<Connection>,
+    ) -> Result<(), TcpError> {
+        if connection.is_none() {
+            let stream =
+                TcpStream::connect(&request.addr).await.map_err(|e| TcpError::Connect(e.kind()))?;
+            stream.set_nodelay(true).ok();
+            self.connect = Some(self.started.elapsed());
+            *connection = Some(Connection { stream, buffer: vec![] });
+        }
+
+        let connection = connection.as_mut().expect("connected above");
+
+        connection.stream.write_all(payload).await.map_err(|e| TcpError::Write(e.kind()))?;
+        self.read(connection, &request.read).await
+    }
+
+    /// Reads a response, starting with anything left over from the last one. Whatever comes in
+    /// after a delimiter is kept for the next response.
+    async fn read(
+        &mut self,
+        connection: &mut Connection,
+        until: &ReadUntil,
+    ) -> Result<(), TcpError> {
+        let Connection { stream, buffer } = connection;
+        let mut chunk = vec![0; 4096];
+        let mut searched = 0;
+
+        loop {
+            match until {
+                ReadUntil::Bytes(n) => {
+                    let take = (n - self.bytes_read).min(buffer.len());
+                    buffer.drain(..take);
+                    self.bytes_read += take;
+                    if self.bytes_read >= *n {
+                        return Ok(());
+                    }
+                }
+                ReadUntil::Delimiter(delimiter) => {
+                    if let Some(at) = find(&buffer[searched..], delimiter) {
+                        let end = searched + at + delimiter.len();
+                        buffer.drain(..end);
+                        self.bytes_read = end;
+                        return Ok(());
+                    }
+
+                    // only the tail can hold the start of a delimiter we haven't seen yet
+                    searched = buffer.len().saturating_sub(delimiter.len().saturating_sub(1));
+                    self.bytes_read = buffer.len();
+                }
+                ReadUntil::Close => {
+                    self.bytes_read += buffer.len();
+                    buffer.clear();
+                }
+            }
+
+            let wanted = match until {
+                ReadUntil::Bytes(n) => (n - self.bytes_read).min(chunk.len()),
+                _ => chunk.len(),
+            };
+
+            let read = match stream.read(&mut chunk[..wanted]).await {
+                Ok(read) => read,
+                Err(e) => return Err(read_error(e)),
+            };
+
+            if read == 0 {
+                return match until {
+                    ReadUntil::Close => Ok(()),
+                    _ => Err(TcpError::Closed),
+                };
+            }
+
+            buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+fn read_error(error: io::Error) -> TcpError {
+    match error.kind() {
+        ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset => TcpError::Closed,
+        kind => TcpError::Read(kind),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    match needle.is_empty() {
+        true => Some(0),
+        false => haystack.windows(needle.len()).position(|window| window == needle),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run;
+    use async_std::{net::TcpListener, task};
+    use futures_await_test::async_test;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers every line with `ok\n`, counting connections
+    async fn line_server(connections: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        task::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                connections.fetch_add(1, Ordering::SeqCst);
+                task::spawn(async move {
+                    let mut buffer = [0; 64];
+                    while let Ok(n) = stream.read(&mut buffer).await {
+                        let lines = buffer[..n].iter().filter(|&&b| b == b'\n').count();
+                        if n == 0 || stream.write_all(&b"ok\n".repeat(lines)).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        addr
+    }
+
+    #[async_test]
+    async fn reuse_connections() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let addr = line_server(connections.clone()).await;
+
+        let request = TcpRequest::new(&addr, b"hello\n".to_vec())
+            .read_until(ReadUntil::Delimiter(b"\n".to_vec()))
+            .with_reuse(true)
+            .with_count(10);
+        let responses = run(tcp_worker, request, 2).await;
+
+        assert_eq!(responses.len(), 20);
+        assert!(responses.iter().all(|r| r.error.is_none() && r.bytes_read == 3));
+        assert_eq!(responses.iter().filter(|r| r.connect.is_some()).count(), 2);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    /// Echoes everything back
+    async fn echo_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        task::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                task::spawn(async move { async_std::io::copy(&stream, &mut &stream).await });
+            }
+        });
+
+        addr
+    }
+
+    #[async_test]
+    async fn keeps_what_follows_a_delimiter() {
+        let addr = echo_server().await;
+
+        // each request gets back two lines; the second is the next request's response
+        let request = TcpRequest::new(&addr, b"a\nbb\n".to_vec())
+            .read_until(ReadUntil::Delimiter(b"\n".to_vec()))
+            .with_reuse(true)
+            .with_count(4);
+        let sizes: Vec<usize> =
+            run(tcp_worker, request, 1).await.iter().map(|r| r.bytes_read).collect();
+        assert_eq!(sizes, vec![2, 3, 2, 3]);
+    }
+
+    #[async_test]
+    async fn errors() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let addr = line_server(connections.clone()).await;
+
+        // asks for more than the server will ever send
+        let request =
+            TcpRequest::new(&addr, b"hi\n".to_vec()).read_until(ReadUntil::Bytes(10)).with_count(1);
+        let timed_out = request.with_timeout(Duration::from_millis(50));
+        let response = run(tcp_worker, timed_out, 1).await.remove(0);
+        assert_eq!(response.error, Some(TcpError::TimedOut));
+        assert_eq!(response.bytes_read, 3);
+
+        // nothing listening once the listener's port is released
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let request = TcpRequest::new(&closed.to_string(), vec![]).with_count(1);
+        let response = run(tcp_worker, request, 1).await.remove(0);
+        assert_eq!(response.error, Some(TcpError::Connect(ErrorKind::ConnectionRefused)));
+    }
+
+    #[async_test]
+    async fn templated_payloads() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let addr = line_server(connections.clone()).await;
+
+        // a payload of two lines gets two answers back
+        let template = PayloadTemplate::new("{{seq}}\n{{seq}}\n").unwrap();
+        let request = TcpRequest::new(&addr, vec![])
+            .with_template(template)
+            .read_until(ReadUntil::Bytes(6))
+            .with_count(3);
+        let responses = run(tcp_worker, request, 1).await;
+        assert_eq!(responses.len(), 3);
+        assert!(responses.iter().all(|r| r.error.is_none() && r.bytes_read == 6));
+
+        // nothing's sent when the template can't be rendered
+        let template = PayloadTemplate::new("{{missing}}\n").unwrap();
+        let request = TcpRequest::new(&addr, vec![]).with_template(template).with_count(3);
+        assert!(run(tcp_worker, request, 1).await.is_empty());
+    }
+}
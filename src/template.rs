@@ -0,0 +1,573 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{http::HttpRequest, rng::Rng};
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    /// The variable's name, as written between the braces
+    Variable(String),
+}
+
+/// # Template
+///
+/// Text with `{{variable}}` placeholders, filled in fresh for every request.
+///
+/// | Variable             | Value                                                        |
+/// |----------------------|--------------------------------------------------------------|
+/// | `{{seq}}`            | Sequence number of the request, from 0                       |
+/// | `{{uuid}}`           | A random version 4 UUID                                      |
+/// | `{{random}}`         | A random 64-bit number                                       |
+/// | `{{random:1-100}}`   | A random number in the range, inclusive                      |
+/// | `{{timestamp}}`      | Seconds since the Unix epoch                                 |
+/// | `{{timestamp_ms}}`   | Milliseconds since the Unix epoch                            |
+/// | `{{anything_else}}`  | That column of the current `Feeder` row                      |
+///
+/// A variable has one value per request, so `{{uuid}}` in a header and in the body is the same
+/// id. `{{` always starts a variable.
+///
+/// `RequestTemplate` and `PayloadTemplate` render templates for the workers; `render` does it
+/// by hand.
+///
+/// ```
+/// use clobber::{Feeder, Template};
+///
+/// let template = Template::parse(r#"{"order": {{seq}}, "user": "{{user}}"}"#).unwrap();
+/// assert_eq!(template.variables(), vec!["seq", "user"]);
+/// assert!(Template::parse("{{seq").is_err());
+///
+/// let users = Feeder::parse("user\nalice\nbob\n").unwrap();
+/// let rendered = template.render(1, 42, Some(&users)).unwrap();
+/// assert_eq!(rendered, r#"{"order": 1, "user": "bob"}"#);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> io::Result<Self> {
+        let mut parts = vec![];
+        let mut rest = template;
+
+        while let Some(open) = rest.find("{{") {
+            if open > 0 {
+                parts.push(Part::Text(rest[..open].to_string()));
+            }
+
+            let after = &rest[open + 2..];
+            let close = match after.find("}}") {
+                Some(close) => close,
+                None => return Err(invalid(format!("unclosed `{{{{` in template: {}", template))),
+            };
+
+            let name = after[..close].trim();
+            if name.is_empty() {
+                return Err(invalid(format!("empty variable in template: {}", template)));
+            }
+
+            parts.push(Part::Variable(name.to_string()));
+            rest = &after[close + 2..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Names of the variables used, in order
+    pub fn variables(&self) -> Vec<&str> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Variable(name) => Some(name.as_str()),
+                Part::Text(_) => None,
+            })
+            .collect()
+    }
+
+    /// Fills in the variables for request number `seq`. Feeder columns come from row `seq`,
+    /// wrapping around at the end, and random values from `seed` and `seq` together, so the
+    /// same arguments always give the same text, timestamps aside.
+    pub fn render(&self, seq: u64, seed: u64, feeder: Option<&Feeder>) -> io::Result<String> {
+        let row = match feeder {
+            Some(feeder) if feeder.is_empty() => return Err(invalid("feeder has no rows".into())),
+            Some(feeder) => Some(seq as usize % feeder.len()),
+            None => None,
+        };
+
+        // a seed per request, so requests don't share random values. Adding `seq` to the seed
+        // would make seed `s + 1` replay seed `s` a request behind.
+        let mixed = Rng::new(seed).next_u64() ^ seq.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        let mut rng = Rng::new(mixed);
+        let mut values =
+            Values { seq, rng: &mut rng, feeder: feeder.zip(row), cache: HashMap::new() };
+        self.fill(&mut values)
+    }
+
+    fn fill(&self, values: &mut Values) -> io::Result<String> {
+        let mut rendered = String::new();
+
+        for part in self.parts.iter() {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Variable(name) => rendered.push_str(values.get(name)?),
+            }
+        }
+
+        Ok(rendered)
+    }
+}
+
+/// # Feeder
+///
+/// Rows of test data from a CSV file, for templates to use by column name.
+///
+/// The first line names the columns. Fields may be quoted to hold commas, with `""` for a
+/// quote, but can't span lines.
+///
+/// ```
+/// use clobber::Feeder;
+///
+/// let feeder = Feeder::parse("user,token\nalice,abc\n\"bob, jr\",def\n").unwrap();
+/// assert_eq!(feeder.len(), 2);
+/// assert_eq!(feeder.get(1, "user"), Some("bob, jr"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feeder {
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Feeder {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses CSV with a header line. Blank lines are skipped.
+    pub fn parse(csv: &str) -> io::Result<Self> {
+        let mut lines = csv.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+        let columns = match lines.next() {
+            Some((number, line)) => fields(line, number)?,
+            None => return Err(invalid("no header line".to_string())),
+        };
+
+        let mut rows = vec![];
+        for (number, line) in lines {
+            let row = fields(line, number)?;
+            if row.len() != columns.len() {
+                let message = format!(
+                    "line {}: expected {} fields, found {}",
+                    number + 1,
+                    columns.len(),
+                    row.len()
+                );
+                return Err(invalid(message));
+            }
+            rows.push(row);
+        }
+
+        Ok(Self { columns, rows })
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The value of `column` in row `row`
+    pub fn get(&self, row: usize, column: &str) -> Option<&str> {
+        let column = self.columns.iter().position(|c| c == column)?;
+        self.rows.get(row).map(|row| row[column].as_str())
+    }
+}
+
+/// Splits one CSV line into fields
+fn fields(line: &str, number: usize) -> io::Result<Vec<String>> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(invalid(format!("line {}: unclosed quote", number + 1)));
+    }
+
+    fields.push(field);
+    Ok(fields)
+}
+
+/// The values of every variable for one request, worked out as they're first used
+struct Values<'a> {
+    seq: u64,
+    rng: &'a mut Rng,
+    feeder: Option<(&'a Feeder, usize)>,
+    cache: HashMap<String, String>,
+}
+
+impl Values<'_> {
+    fn get(&mut self, name: &str) -> io::Result<&str> {
+        if !self.cache.contains_key(name) {
+            let value = self.value(name)?;
+            self.cache.insert(name.to_string(), value);
+        }
+
+        Ok(&self.cache[name])
+    }
+
+    fn value(&mut self, name: &str) -> io::Result<String> {
+        let since_epoch = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let value = match name {
+            "seq" => self.seq.to_string(),
+            "uuid" => uuid(self.rng),
+            "random" => self.rng.next_u64().to_string(),
+            "timestamp" => since_epoch().as_secs().to_string(),
+            "timestamp_ms" => since_epoch().as_millis().to_string(),
+            _ if name.starts_with("random:") => {
+                let (low, high) = range(&name["random:".len()..])
+                    .ok_or_else(|| invalid(format!("bad range in `{}`", name)))?;
+                let span = (high - low).wrapping_add(1);
+                match span {
+                    0 => self.rng.next_u64().to_string(),
+                    span => (low + self.rng.next_u64() % span).to_string(),
+                }
+            }
+            column => match self.feeder {
+                Some((feeder, row)) => match feeder.get(row, column) {
+                    Some(value) => value.to_string(),
+                    None => return Err(invalid(format!("no feeder column `{}`", column))),
+                },
+                None => return Err(invalid(format!("unknown variable `{}`", column))),
+            },
+        };
+
+        Ok(value)
+    }
+}
+
+/// Parses `low-high`
+fn range(range: &str) -> Option<(u64, u64)> {
+    let dash = range.find('-')?;
+    let low = range[..dash].trim().parse().ok()?;
+    let high = range[dash + 1..].trim().parse().ok()?;
+    match low <= high {
+        true => Some((low, high)),
+        false => None,
+    }
+}
+
+fn uuid(rng: &mut Rng) -> String {
+    let high = (rng.next_u64() & !0xf000) | 0x4000;
+    let low = (rng.next_u64() & !(0b11 << 62)) | (0b10 << 62);
+
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff,
+    )
+}
+
+/// # RequestTemplate
+///
+/// An HTTP request whose url, headers, and body are `Template`s, rendered into a different
+/// `HttpRequest` every time.
+///
+/// Everything but the timestamps comes from the seed: the same seed and feeder give the same
+/// sequence of requests, run after run. Feeder rows are used in order, wrapping around at the
+/// end, or picked at random with `with_random_rows`.
+///
+/// To put templated requests through a `WorkerPool`, add them to a `TrafficMix` with
+/// `with_templated_endpoint`.
+///
+/// ```
+/// use clobber::{Feeder, RequestTemplate};
+///
+/// let users = Feeder::parse("user\nalice\nbob\n").unwrap();
+/// let mut template = RequestTemplate::new("POST", "http://localhost:8000/users/{{user}}/orders")
+///     .unwrap()
+///     .with_header("X-Request-Id", "{{uuid}}")
+///     .unwrap()
+///     .with_body(r#"{"order": {{seq}}, "quantity": {{random:1-5}}}"#)
+///     .unwrap()
+///     .with_feeder(users)
+///     .with_seed(42);
+///
+/// let first = template.render().unwrap();
+/// let second = template.render().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RequestTemplate {
+    method: String,
+    url: Template,
+    headers: Vec<(String, Template)>,
+    body: Option<Template>,
+    timeout: Option<Duration>,
+    feeder: Option<Feeder>,
+    random_rows: bool,
+    rng: Rng,
+    seq: u64,
+}
+
+impl RequestTemplate {
+    /// Fails if the url isn't a valid template
+    pub fn new(method: &str, url: &str) -> io::Result<Self> {
+        Ok(Self {
+            method: method.to_string(),
+            url: Template::parse(url)?,
+            headers: vec![],
+            body: None,
+            timeout: None,
+            feeder: None,
+            random_rows: false,
+            rng: Rng::new(0),
+            seq: 0,
+        })
+    }
+
+    /// Adds a header whose value is a template
+    pub fn with_header(mut self, name: &str, value: &str) -> io::Result<Self> {
+        self.headers.push((name.to_string(), Template::parse(value)?));
+        Ok(self)
+    }
+
+    pub fn with_body(mut self, body: &str) -> io::Result<Self> {
+        self.body = Some(Template::parse(body)?);
+        Ok(self)
+    }
+
+    /// Gives up on each request, including connecting, after `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_feeder(mut self, feeder: Feeder) -> Self {
+        self.feeder = Some(feeder);
+        self
+    }
+
+    /// Picks feeder rows at random instead of in order
+    pub fn with_random_rows(mut self, random: bool) -> Self {
+        self.random_rows = random;
+        self
+    }
+
+    /// Seeds the random variables, and the row order with `with_random_rows`
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    /// The next request. Fails if a template uses a variable there's no value for, or the
+    /// rendered url isn't a valid `http://` url.
+    pub fn render(&mut self) -> io::Result<HttpRequest> {
+        let row = match self.feeder.as_ref() {
+            Some(feeder) if feeder.is_empty() => return Err(invalid("feeder has no rows".into())),
+            Some(feeder) => match self.random_rows {
+                true => Some(self.rng.next_u64() as usize % feeder.len()),
+                false => Some(self.seq as usize % feeder.len()),
+            },
+            None => None,
+        };
+
+        let mut values = Values {
+            seq: self.seq,
+            rng: &mut self.rng,
+            feeder: self.feeder.as_ref().zip(row),
+            cache: HashMap::new(),
+        };
+        self.seq += 1;
+
+        let mut request = HttpRequest::new(&self.method, &self.url.fill(&mut values)?)?;
+        for (name, value) in self.headers.iter() {
+            request = request.with_header(name, &value.fill(&mut values)?);
+        }
+        if let Some(body) = self.body.as_ref() {
+            request = request.with_body(body.fill(&mut values)?.into_bytes());
+        }
+        if let Some(timeout) = self.timeout {
+            request = request.with_timeout(timeout);
+        }
+
+        Ok(request)
+    }
+}
+
+/// # PayloadTemplate
+///
+/// A raw payload that's a `Template`, for `TcpRequest::with_template` and
+/// `UdpRequest::with_template`. Every request sends a freshly rendered payload.
+///
+/// Clones of a request share the template, so `{{seq}}` counts up across every worker sending
+/// it rather than per worker, and feeder rows are handed out in order between them.
+///
+/// ```
+/// use clobber::{PayloadTemplate, TcpRequest};
+///
+/// let set = PayloadTemplate::new("SET key:{{seq}} {{random:1-1000}}\r\n").unwrap().with_seed(7);
+/// let request = TcpRequest::new("127.0.0.1:6379", vec![]).with_template(set);
+/// ```
+#[derive(Debug)]
+pub struct PayloadTemplate {
+    template: Template,
+    feeder: Option<Feeder>,
+    seed: u64,
+    seq: AtomicU64,
+}
+
+impl PayloadTemplate {
+    /// Fails if `template` isn't a valid template
+    pub fn new(template: &str) -> io::Result<Self> {
+        Ok(Self {
+            template: Template::parse(template)?,
+            feeder: None,
+            seed: 0,
+            seq: AtomicU64::new(0),
+        })
+    }
+
+    pub fn with_feeder(mut self, feeder: Feeder) -> Self {
+        self.feeder = Some(feeder);
+        self
+    }
+
+    /// Seeds the random variables
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The next payload. Fails if the template uses a variable there's no value for.
+    pub fn render(&self) -> io::Result<Vec<u8>> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let rendered = self.template.render(seq, self.seed, self.feeder.as_ref())?;
+        Ok(rendered.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_csv() {
+        let feeder = Feeder::parse("a,b\r\n1,\"x,\"\"y\"\"\"\n\n2,\n").unwrap();
+        assert_eq!(feeder.columns(), ["a", "b"]);
+        assert_eq!(feeder.get(0, "b"), Some("x,\"y\""));
+        assert_eq!(feeder.get(1, "b"), Some(""));
+        assert_eq!(feeder.get(0, "c"), None);
+
+        let error = Feeder::parse("a,b\n1,2\n3\n").unwrap_err();
+        assert!(error.to_string().starts_with("line 3"), "{}", error);
+        assert!(Feeder::parse("a\n\"1\n").is_err());
+    }
+
+    fn rendered(template: &mut RequestTemplate) -> String {
+        String::from_utf8(template.render().unwrap().encode()).unwrap()
+    }
+
+    #[test]
+    fn render() {
+        let feeder = Feeder::parse("user\nalice\nbob\n").unwrap();
+        let template = RequestTemplate::new("POST", "http://a/{{user}}/{{seq}}")
+            .unwrap()
+            .with_header("X-Id", "{{uuid}}")
+            .unwrap()
+            .with_body("{{uuid}} {{random:5-7}} {{timestamp}}")
+            .unwrap()
+            .with_feeder(feeder)
+            .with_seed(9);
+
+        let mut a = template.clone();
+        let first = rendered(&mut a);
+        assert!(first.starts_with("POST /alice/0 HTTP/1.1\r\n"), "{}", first);
+        assert!(rendered(&mut a).starts_with("POST /bob/1 "));
+        assert!(rendered(&mut a).starts_with("POST /alice/2 "));
+
+        // one uuid per request, shared by the header and body
+        let id = first.lines().find_map(|line| line.strip_prefix("X-Id: ")).unwrap();
+        let body = first.rsplit("\r\n").next().unwrap();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert!(body.starts_with(id));
+
+        // the same seed gives the same requests, apart from the timestamp
+        let mut b = template.clone();
+        let strip = |s: String| s[..s.rfind(' ').unwrap()].to_string();
+        assert_eq!(strip(first), strip(rendered(&mut b)));
+
+        let mut numbers = template.clone().with_body("{{random:5-7}}").unwrap();
+        for _ in 0..50 {
+            let body = rendered(&mut numbers);
+            let n: u64 = body.rsplit("\r\n").next().unwrap().parse().unwrap();
+            assert!((5..=7).contains(&n));
+        }
+
+        let mut missing = RequestTemplate::new("GET", "http://a/{{nope}}").unwrap();
+        assert!(missing.render().is_err());
+    }
+
+    #[test]
+    fn render_payloads() {
+        let template = Template::parse("{{seq}} {{random}}").unwrap();
+        let first = template.render(3, 1, None).unwrap();
+        assert!(first.starts_with("3 "));
+        assert_eq!(first, template.render(3, 1, None).unwrap());
+        assert_ne!(first, template.render(3, 2, None).unwrap());
+        assert_ne!(first[2..], template.render(4, 1, None).unwrap()[2..]);
+
+        // neighbouring seeds aren't the same stream shifted by a request
+        let random = Template::parse("{{random}}").unwrap();
+        for seq in 0..16 {
+            let next_seed = random.render(seq, 8, None).unwrap();
+            assert_ne!(random.render(seq + 1, 7, None).unwrap(), next_seed);
+        }
+
+        let feeder = Feeder::parse("word\nping\npong\n").unwrap();
+        let payload = PayloadTemplate::new("{{word}} {{seq}}\n").unwrap().with_feeder(feeder);
+        let payloads: Vec<Vec<u8>> = (0..3).map(|_| payload.render().unwrap()).collect();
+        assert_eq!(
+            payloads,
+            vec![b"ping 0\n".to_vec(), b"pong 1\n".to_vec(), b"ping 2\n".to_vec()]
+        );
+        assert!(PayloadTemplate::new("{{word}}").unwrap().render().is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! Fixtures shared by the tests of the workload modules
+
+use async_std::{
+    io::{prelude::*, BufReader},
+    net::TcpListener,
+    sync::channel,
+    task,
+};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::pool::{Job, JobStatus, WorkerPool};
+
+/// Serves HTTP on a free local port, answering each request on a connection with
+/// `respond(head)` and counting connections. Returns the server's address.
+///
+/// Only request heads are read, so requests sent to it shouldn't have bodies.
+pub(crate) async fn http_server<R>(respond: R, connections: Arc<AtomicUsize>) -> String
+where
+    R: Fn(&[u8]) -> &'static [u8] + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let respond = Arc::new(respond);
+
+    task::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            connections.fetch_add(1, Ordering::SeqCst);
+            let respond = respond.clone();
+            task::spawn(async move {
+                let mut reader = BufReader::new(&stream);
+                let mut head = vec![];
+                while let Ok(n) = reader.read_until(b'\n', &mut head).await {
+                    if n == 0 {
+                        break;
+                    }
+                    if !head.ends_with(b"\r\n\r\n") {
+                        continue;
+                    }
+                    if (&stream).write_all(respond(&head)).await.is_err() {
+                        break;
+                    }
+                    head.clear();
+                }
+            });
+        }
+    });
+
+    addr
+}
+
+/// Runs a copy of `task` for each of `workers` workers, and returns everything they sent
+pub(crate) async fn run<In, Out, F>(
+    worker: fn(Job<In, Out>) -> F,
+    task: In,
+    workers: usize,
+) -> Vec<Out>
+where
+    In: Clone + Send + Sync + 'static,
+    Out: Send + Sync + 'static,
+    F: Future<Output = JobStatus> + Send + 'static,
+{
+    let (send, recv) = channel(1024);
+    let mut pool = WorkerPool::new(worker, send, workers);
+    for _ in 0..workers {
+        pool.push(task.clone());
+    }
+
+    pool.work().await;
+
+    let mut outputs = vec![];
+    while let Ok(out) = recv.try_recv() {
+        outputs.push(out);
+    }
+    outputs
+}
@@ -0,0 +1,405 @@
+use async_std::{future, net::UdpSocket};
+use log::warn;
+use std::{
+    io::ErrorKind,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    histogram::Histogram,
+    pool::{Job, JobStatus},
+    sample::Sample,
+    template::PayloadTemplate,
+};
+
+/// Largest datagram we expect back
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+/// # UdpRequest
+///
+/// A datagram for `udp_worker` to send over and over, either fire-and-forget (statsd and
+/// friends) or waiting for a reply (DNS and friends).
+///
+/// When waiting for replies, a reply that shows up after its request gave up could be
+/// mistaken for the answer to the next request. `with_id_at` avoids that: each request gets a
+/// sequence number written into the payload, and only a reply carrying the same bytes counts.
+///
+/// The send rate is the pool's business: set a rate limit or run the pool open-loop.
+///
+/// ```no_run
+/// # use async_std::{sync::channel, task};
+/// use clobber::{udp_worker, UdpRequest, UdpSummary, WorkerPool};
+/// use std::time::Duration;
+///
+/// // a DNS query for example.com, with its 2-byte transaction id at the front
+/// let query = b"\0\0\x01\0\0\x01\0\0\0\0\0\0\x07example\x03com\0\0\x01\0\x01".to_vec();
+/// let request = UdpRequest::new("127.0.0.1:53", query)
+///     .with_reply(Duration::from_millis(500))
+///     .with_id_at(0, 2);
+///
+/// let (send, recv) = channel(1024);
+/// let mut pool = WorkerPool::new(udp_worker, send, 4);
+/// pool.set_rate_limit(Some(1000.0), 10);
+/// for _ in 0..4 {
+///     pool.push(request.clone());
+/// }
+///
+/// let summary = task::spawn(async move {
+///     let mut summary = UdpSummary::new();
+///     while let Ok(response) = recv.recv().await {
+///         summary.add(&response);
+///     }
+///     summary
+/// });
+/// task::block_on(pool.work());
+/// ```
+#[derive(Debug, Clone)]
+pub struct UdpRequest {
+    addr: String,
+    payload: Arc<Vec<u8>>,
+    template: Option<Arc<PayloadTemplate>>,
+    reply_timeout: Option<Duration>,
+    id: Option<(usize, usize)>,
+    count: Option<usize>,
+}
+
+impl UdpRequest {
+    /// Sends `payload` to `addr` without waiting for anything back
+    pub fn new(addr: &str, payload: Vec<u8>) -> Self {
+        Self {
+            addr: addr.to_string(),
+            payload: Arc::new(payload),
+            template: None,
+            reply_timeout: None,
+            id: None,
+            count: None,
+        }
+    }
+
+    /// Waits up to `timeout` for a reply to each datagram. No reply in time counts as lost.
+    pub fn with_reply(mut self, timeout: Duration) -> Self {
+        self.reply_timeout = Some(timeout);
+        self
+    }
+
+    /// Writes a big-endian sequence number into the `len` bytes of the payload starting at
+    /// `offset`, and matches replies on the same bytes. `len` is at most 8.
+    ///
+    /// Panics if the payload is too short to hold the id. Templated payloads are checked as
+    /// they're rendered instead.
+    pub fn with_id_at(mut self, offset: usize, len: usize) -> Self {
+        let len = len.min(8);
+        let fits = self.template.is_some() || offset + len <= self.payload.len();
+        assert!(fits, "id doesn't fit in the payload");
+        self.id = Some((offset, len));
+        self
+    }
+
+    /// Sends this many datagrams and finishes, instead of looping until stopped
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    /// Sends a payload rendered from `template` each time, instead of the same bytes
+    pub fn with_template(mut self, template: PayloadTemplate) -> Self {
+        self.template = Some(Arc::new(template));
+        self
+    }
+}
+
+/// Where a request went wrong
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UdpError {
+    Bind(ErrorKind),
+    Send(ErrorKind),
+    Recv(ErrorKind),
+    /// No matching reply before the timeout
+    Lost,
+}
+
+/// The outcome of one datagram
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UdpResponse {
+    pub started: Instant,
+    /// Time to send, or time to get the reply when waiting for one
+    pub latency: Duration,
+    pub bytes_sent: usize,
+    /// Size of the matching reply, if there was one
+    pub reply_size: Option<usize>,
+    pub error: Option<UdpError>,
+}
+
+impl Sample for UdpResponse {
+    fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// # udp_worker
+///
+/// A worker for `WorkerPool` that repeats a `UdpRequest`, sending a `UdpResponse` for every
+/// datagram.
+///
+/// Each worker sends from its own socket, one datagram at a time. It runs until stopped, or
+/// until it's sent the request's `count` of datagrams. Jobs started by an open-loop pool send
+/// a single datagram. Each send waits on the pool's rate limiter first.
+///
+/// A job whose payload template can't be rendered, or renders too short to hold the id, fails
+/// without sending anything.
+pub async fn udp_worker(job: Job<UdpRequest, UdpResponse>) -> JobStatus {
+    let request = &job.task;
+    let mut remaining = match job.scheduled {
+        Some(_) => Some(1),
+        None => request.count,
+    };
+
+    let socket = match bind(&request.addr).await {
+        Ok(socket) => socket,
+        Err(error) => {
+            let response = UdpResponse {
+                started: Instant::now(),
+                latency: Duration::from_secs(0),
+                bytes_sent: 0,
+                reply_size: None,
+                error: Some(error),
+            };
+            job.results.send(response).await;
+            return JobStatus::Failed;
+        }
+    };
+
+    let mut payload = request.payload.to_vec();
+    let mut reply = vec![0; MAX_DATAGRAM];
+    let mut sequence: u64 = 0;
+
+    loop {
+        if job.stop_requested() {
+            return JobStatus::Stopped;
+        }
+
+        match remaining {
+            Some(0) => return JobStatus::Done,
+            Some(n) => remaining = Some(n - 1),
+            None => {}
+        }
+
+        if let Some(template) = request.template.as_ref() {
+            payload = match template.render() {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!("couldn't render a payload for {}: {}", request.addr, e);
+                    return JobStatus::Failed;
+                }
+            };
+        }
+
+        if let Some((offset, len)) = request.id {
+            if offset + len > payload.len() {
+                warn!("id doesn't fit in a {} byte payload for {}", payload.len(), request.addr);
+                return JobStatus::Failed;
+            }
+            let bytes = sequence.to_be_bytes();
+            payload[offset..offset + len].copy_from_slice(&bytes[8 - len..]);
+        }
+        sequence = sequence.wrapping_add(1);
+
+        job.limiter.acquire().await;
+
+        let started = Instant::now();
+        let mut response = UdpResponse {
+            started,
+            latency: Duration::from_secs(0),
+            bytes_sent: 0,
+            reply_size: None,
+            error: None,
+        };
+
+        match socket.send(&payload).await {
+            Ok(sent) => response.bytes_sent = sent,
+            Err(e) => response.error = Some(UdpError::Send(e.kind())),
+        }
+
+        if let (Some(timeout), None) = (request.reply_timeout, response.error) {
+            let id = request.id.map(|(offset, len)| (offset, &payload[offset..offset + len]));
+            match await_reply(&socket, &mut reply, id, started + timeout).await {
+                Ok(size) => response.reply_size = Some(size),
+                Err(error) => response.error = Some(error),
+            }
+        }
+
+        response.latency = started.elapsed();
+        job.results.send(response).await;
+    }
+}
+
+/// A socket that only talks to `addr`
+async fn bind(addr: &str) -> Result<UdpSocket, UdpError> {
+    let local = match addr.starts_with('[') {
+        true => "[::]:0",
+        false => "0.0.0.0:0",
+    };
+
+    let socket = UdpSocket::bind(local).await.map_err(|e| UdpError::Bind(e.kind()))?;
+    socket.connect(addr).await.map_err(|e| UdpError::Bind(e.kind()))?;
+    Ok(socket)
+}
+
+/// Waits for a reply carrying `id` at the same offset, skipping replies to earlier requests
+async fn await_reply(
+    socket: &UdpSocket,
+    buffer: &mut [u8],
+    id: Option<(usize, &[u8])>,
+    deadline: Instant,
+) -> Result<usize, UdpError> {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let size = match future::timeout(remaining, socket.recv(buffer)).await {
+            Ok(Ok(size)) => size,
+            Ok(Err(e)) => return Err(UdpError::Recv(e.kind())),
+            Err(_) => return Err(UdpError::Lost),
+        };
+
+        let matches = match id {
+            Some((offset, id)) => buffer[..size].get(offset..offset + id.len()) == Some(id),
+            None => true,
+        };
+
+        if matches {
+            return Ok(size);
+        }
+    }
+}
+
+/// # UdpSummary
+///
+/// Adds up `UdpResponse`s into loss and latency figures.
+#[derive(Debug, Clone, Default)]
+pub struct UdpSummary {
+    pub sent: u64,
+    pub replies: u64,
+    pub lost: u64,
+    /// Other failures: binding, sending, receiving
+    pub errors: u64,
+    /// Latency of the datagrams that got a reply, or of sending them when not waiting for one
+    pub latency: Histogram,
+}
+
+impl UdpSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, response: &UdpResponse) {
+        if response.bytes_sent > 0 {
+            self.sent += 1;
+        }
+
+        match response.error {
+            None => self.latency.record(response.latency),
+            Some(UdpError::Lost) => self.lost += 1,
+            Some(_) => self.errors += 1,
+        }
+
+        if response.reply_size.is_some() {
+            self.replies += 1;
+        }
+    }
+
+    /// Fraction of sent datagrams that never got a reply, from 0 to 1
+    pub fn loss_rate(&self) -> f32 {
+        match self.sent {
+            0 => 0.0,
+            sent => self.lost as f32 / sent as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run;
+    use async_std::task;
+    use futures_await_test::async_test;
+
+    /// Echoes datagrams back, first holding back every other one until the next arrives, so
+    /// those replies show up late and out of order
+    async fn late_echo_server() -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+
+        task::spawn(async move {
+            let mut buffer = [0; 64];
+            let mut held: Option<Vec<u8>> = None;
+            while let Ok((size, peer)) = socket.recv_from(&mut buffer).await {
+                let datagram = buffer[..size].to_vec();
+                if let Some(late) = held.take() {
+                    socket.send_to(&late, peer).await.ok();
+                    socket.send_to(&datagram, peer).await.ok();
+                } else {
+                    held = Some(datagram);
+                }
+            }
+        });
+
+        addr
+    }
+
+    async fn summary(request: UdpRequest) -> UdpSummary {
+        let mut summary = UdpSummary::new();
+        for response in run(udp_worker, request, 1).await {
+            summary.add(&response);
+        }
+        summary
+    }
+
+    #[async_test]
+    async fn matches_replies() {
+        let addr = late_echo_server().await;
+        let request = UdpRequest::new(&addr, b"id:----".to_vec())
+            .with_reply(Duration::from_millis(50))
+            .with_id_at(3, 4)
+            .with_count(10);
+
+        // every held datagram's reply comes after its request gave up, and must not be
+        // mistaken for the reply to the next one
+        let summary = summary(request).await;
+        assert_eq!(summary.sent, 10);
+        assert_eq!(summary.lost, 5);
+        assert_eq!(summary.replies, 5);
+        assert_eq!(summary.loss_rate(), 0.5);
+    }
+
+    #[async_test]
+    async fn fire_and_forget() {
+        let addr = late_echo_server().await;
+        let summary = summary(UdpRequest::new(&addr, b"stat:1|c".to_vec()).with_count(20)).await;
+
+        assert_eq!(summary.sent, 20);
+        assert_eq!(summary.replies, 0);
+        assert_eq!(summary.latency.count(), 20);
+        assert_eq!(summary.loss_rate(), 0.0);
+    }
+
+    #[async_test]
+    async fn templated_payloads() {
+        let addr = late_echo_server().await;
+        let template = PayloadTemplate::new("stat:{{seq}}|c").unwrap();
+        let request = UdpRequest::new(&addr, vec![]).with_template(template).with_count(12);
+
+        // the payload grows a byte once the sequence number reaches two digits
+        let sent: Vec<usize> =
+            run(udp_worker, request, 1).await.iter().map(|r| r.bytes_sent).collect();
+        assert_eq!(sent, [vec![8; 10], vec![9; 2]].concat());
+
+        // an id that doesn't fit the rendered payload fails the job before sending
+        let template = PayloadTemplate::new("{{seq}}").unwrap();
+        let request = UdpRequest::new(&addr, vec![]).with_template(template).with_id_at(0, 2);
+        assert!(run(udp_worker, request.with_count(1), 1).await.is_empty());
+    }
+}